@@ -0,0 +1,421 @@
+//! Headless compiler sharing the editor's compile and export pipeline.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use typst::layout::PagedDocument;
+use typst_editor_lib::export::{self, ExportFormat};
+use typst_editor_lib::packages::{DownloadListener, DownloadStatus};
+use typst_editor_lib::settings::{self, Settings};
+use typst_editor_lib::world::EditorWorld;
+
+/// Directories under the project root whose changes never trigger a recompile.
+const IGNORED_DIRS: &[&str] = &[".git", "target", "node_modules"];
+
+const USAGE: &str = "\
+Usage: typst-editor-cli <input.typ> [output] [options]
+
+Options:
+  -f, --format <pdf|png|svg>  Output format (default: from the output extension, else pdf)
+      --ppi <number>          Pixels per inch for PNG output (default: 144)
+      --input <key=value>     Add a sys.inputs entry; overrides typst-inputs.toml
+      --settings <file>       Editor settings JSON (packages, pinned date, ...)
+  -w, --watch                 Recompile whenever a file in the project folder changes
+  -h, --help                  Print this help
+
+Multi-page PNG and SVG output writes one file per page; use `{p}` in the
+output name for the page number, otherwise `-<page>` is appended.
+
+Set SOURCE_DATE_EPOCH to a Unix timestamp to fix the compile date for
+reproducible output; it takes precedence over a pinned date.";
+
+/// Exit code for a successful compilation.
+const EXIT_SUCCESS: u8 = 0;
+/// Exit code for documents that fail to compile.
+const EXIT_COMPILE_ERROR: u8 = 1;
+/// Exit code for invalid arguments and I/O errors.
+const EXIT_USAGE_ERROR: u8 = 2;
+/// How long to wait for more file events before recompiling in watch mode.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+
+struct Options {
+    input: PathBuf,
+    output: PathBuf,
+    format: ExportFormat,
+    ppi: f32,
+    inputs: HashMap<String, String>,
+    settings: Settings,
+    watch: bool,
+}
+
+/// The result of one compilation.
+struct Outcome {
+    success: bool,
+    /// Formatted errors and warnings.
+    diagnostics: Vec<String>,
+    /// Files written by the export.
+    written: Vec<PathBuf>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut format = None;
+        let mut ppi = 144.0;
+        let mut inputs = HashMap::new();
+        let mut settings = Settings::default();
+        let mut watch = false;
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("Missing value for {}", name));
+            match arg.as_str() {
+                "-f" | "--format" => format = Some(value(&arg)?.parse()?),
+                "--ppi" => {
+                    ppi = value(&arg)?
+                        .parse()
+                        .map_err(|e| format!("Invalid --ppi: {}", e))?
+                }
+                "--input" => {
+                    let pair = value(&arg)?;
+                    let (key, val) = pair
+                        .split_once('=')
+                        .ok_or_else(|| format!("Invalid --input {:?}, expected key=value", pair))?;
+                    inputs.insert(key.to_string(), val.to_string());
+                }
+                // An explicitly passed file must be valid, unlike the editor's own settings
+                "--settings" => settings = Settings::read(Path::new(&value(&arg)?))?,
+                "-w" | "--watch" => watch = true,
+                _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+                _ => positional.push(PathBuf::from(arg)),
+            }
+        }
+        // Like the typst CLI, always honour SOURCE_DATE_EPOCH
+        settings.compile.source_date_epoch = true;
+
+        let mut positional = positional.into_iter();
+        let input = positional.next().ok_or("Missing input file")?;
+        let output = positional.next();
+        if positional.next().is_some() {
+            return Err("Too many arguments".to_string());
+        }
+
+        let format = format
+            .or_else(|| output.as_deref().and_then(ExportFormat::from_path))
+            .unwrap_or(ExportFormat::Pdf);
+        let output = output.unwrap_or_else(|| input.with_extension(format.extension()));
+
+        Ok(Options { input, output, format, ppi, inputs, settings, watch })
+    }
+
+    /// The project's inputs file overlaid with `--input` values.
+    fn resolved_inputs(&self) -> HashMap<String, String> {
+        let mut inputs = self
+            .input
+            .parent()
+            .map(settings::load_project_inputs)
+            .unwrap_or_default();
+        inputs.extend(self.inputs.clone());
+        inputs
+    }
+
+    /// The folder watched for changes, which is also the compile root.
+    fn root(&self) -> PathBuf {
+        match self.input.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
+}
+
+/// Prints package downloads to stderr; inline downloads otherwise look like a hang.
+fn download_listener() -> DownloadListener {
+    Arc::new(|event| match event.status {
+        DownloadStatus::Started => eprintln!("downloading {}", event.package),
+        DownloadStatus::Failed => eprintln!(
+            "failed to download {}: {}",
+            event.package,
+            event.error.unwrap_or_default()
+        ),
+        _ => {}
+    })
+}
+
+fn read_input(options: &Options) -> Result<String, String> {
+    std::fs::read_to_string(&options.input)
+        .map_err(|e| format!("Failed to read {}: {}", options.input.display(), e))
+}
+
+/// Compiles the current state of `world` and writes the output.
+fn compile(world: &EditorWorld, options: &Options) -> Result<Outcome, String> {
+    let result = typst::compile::<PagedDocument>(world);
+    let mut diagnostics: Vec<String> = result
+        .warnings
+        .iter()
+        .map(|warning| export::format_diagnostic(world, warning))
+        .collect();
+
+    let document = match result.output {
+        Ok(document) => document,
+        Err(errors) => {
+            diagnostics.extend(errors.iter().map(|error| export::format_diagnostic(world, error)));
+            return Ok(Outcome { success: false, diagnostics, written: vec![] });
+        }
+    };
+
+    let mut written = Vec::new();
+    match options.format {
+        ExportFormat::Pdf => {
+            let pdf = export::pdf(&document, &options.settings.compile)?;
+            written.push(write(options.output.clone(), &pdf)?);
+        }
+        ExportFormat::Svg => {
+            let pages = export::svg_pages(&document);
+            for (index, svg) in pages.iter().enumerate() {
+                written.push(write(page_path(&options.output, index, pages.len()), svg.as_bytes())?);
+            }
+        }
+        ExportFormat::Png => {
+            let pages = export::png_pages(&document, options.ppi)?;
+            for (index, png) in pages.iter().enumerate() {
+                written.push(write(page_path(&options.output, index, pages.len()), png)?);
+            }
+        }
+    }
+    Ok(Outcome { success: true, diagnostics, written })
+}
+
+/// Compiles the input once, printing diagnostics to stderr.
+fn compile_once(options: &Options) -> Result<bool, String> {
+    let world = EditorWorld::new(&read_input(options)?, Some(&options.input), &options.settings)
+        .with_inputs(options.resolved_inputs())
+        .with_download_listener(download_listener());
+
+    let outcome = compile(&world, options)?;
+    for diagnostic in &outcome.diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    Ok(outcome.success)
+}
+
+/// Recompiles whenever a file under the project root changes, reporting each
+/// run on stdout. Runs until the process is interrupted.
+fn watch(options: &Options) -> Result<(), String> {
+    let root = options.root();
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(|e| e.to_string())?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(|e| format!("Failed to watch {}: {}", root.display(), e))?;
+    println!("watching {}", root.display());
+    let root = root.canonicalize().unwrap_or(root);
+
+    let mut world = EditorWorld::new(&read_input(options)?, Some(&options.input), &options.settings)
+        .with_download_listener(download_listener());
+    // The output may not exist yet, so it is also matched by its absolute path
+    let mut written: HashSet<PathBuf> = std::path::absolute(&options.output).into_iter().collect();
+
+    loop {
+        let start = Instant::now();
+        let result = read_input(options).and_then(|content| {
+            // Passing the path again drops cached sources of other project files
+            world.update_source(&content, Some(&options.input));
+            world.set_inputs(options.resolved_inputs());
+            compile(&world, options)
+        });
+        // Drop memoized results the last few compiles did not use
+        comemo::evict(10);
+        let elapsed = start.elapsed().as_millis();
+        let time = chrono::Local::now().format("%H:%M:%S");
+
+        match result {
+            Ok(outcome) => {
+                let status = if outcome.success { "compiled successfully" } else { "compiled with errors" };
+                println!("[{}] {} in {} ms", time, status, elapsed);
+                for diagnostic in &outcome.diagnostics {
+                    println!("{}", diagnostic);
+                }
+                written.extend(outcome.written.iter().filter_map(|path| path.canonicalize().ok()));
+            }
+            Err(e) => println!("[{}] error: {}", time, e),
+        }
+
+        wait_for_change(&rx, &root, &written)?;
+    }
+}
+
+/// Blocks until a file other than our own output or one in [`IGNORED_DIRS`]
+/// changes, then waits for the burst of events from a single save to settle.
+fn wait_for_change(
+    rx: &mpsc::Receiver<notify::Result<notify::Event>>,
+    root: &Path,
+    written: &HashSet<PathBuf>,
+) -> Result<(), String> {
+    let relevant = |event: notify::Result<notify::Event>| match event {
+        Ok(event) => {
+            !matches!(event.kind, EventKind::Access(_))
+                && event.paths.iter().any(|path| !is_ignored(root, path, written))
+        }
+        Err(e) => {
+            println!("watch error: {}", e);
+            false
+        }
+    };
+
+    loop {
+        let event = rx.recv().map_err(|_| "File watcher stopped".to_string())?;
+        if relevant(event) {
+            break;
+        }
+    }
+    while rx.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+    Ok(())
+}
+
+fn is_ignored(root: &Path, path: &Path, written: &HashSet<PathBuf>) -> bool {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    written.contains(&path)
+        || path.strip_prefix(root).is_ok_and(|relative| {
+            relative
+                .components()
+                .any(|component| IGNORED_DIRS.iter().any(|dir| component.as_os_str() == *dir))
+        })
+}
+
+/// The output path of page `index` when exporting one file per page.
+fn page_path(output: &Path, index: usize, count: usize) -> PathBuf {
+    let name = output.to_string_lossy();
+    if name.contains("{p}") {
+        return PathBuf::from(name.replace("{p}", &(index + 1).to_string()));
+    }
+    if count == 1 {
+        return output.to_path_buf();
+    }
+
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match output.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, index + 1, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, index + 1),
+    };
+    output.with_file_name(file_name)
+}
+
+fn write(path: PathBuf, data: &[u8]) -> Result<PathBuf, String> {
+    std::fs::write(&path, data).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return if args.is_empty() { ExitCode::from(EXIT_USAGE_ERROR) } else { ExitCode::SUCCESS };
+    }
+
+    let options = match Options::parse(args.into_iter()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(EXIT_USAGE_ERROR);
+        }
+    };
+
+    let result = match options.watch {
+        true => watch(&options).map(|()| true),
+        false => compile_once(&options),
+    };
+    if let Err(e) = &result {
+        eprintln!("error: {}", e);
+    }
+    ExitCode::from(exit_code(&result))
+}
+
+/// Maps whether the document compiled, or why the run failed, to the exit code.
+fn exit_code(result: &Result<bool, String>) -> u8 {
+    match result {
+        Ok(true) => EXIT_SUCCESS,
+        Ok(false) => EXIT_COMPILE_ERROR,
+        Err(_) => EXIT_USAGE_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_defaults_to_pdf_next_to_the_input() {
+        let options = parse(&["doc/main.typ"]).unwrap();
+        assert_eq!(options.input, Path::new("doc/main.typ"));
+        assert_eq!(options.output, Path::new("doc/main.pdf"));
+        assert_eq!(options.format, ExportFormat::Pdf);
+        assert_eq!(options.ppi, 144.0);
+        assert!(!options.watch);
+        assert!(options.settings.compile.source_date_epoch);
+    }
+
+    #[test]
+    fn parse_takes_the_format_from_the_output_or_the_flag() {
+        assert_eq!(parse(&["main.typ", "out.png"]).unwrap().format, ExportFormat::Png);
+
+        let options = parse(&["main.typ", "-f", "svg"]).unwrap();
+        assert_eq!(options.format, ExportFormat::Svg);
+        assert_eq!(options.output, Path::new("main.svg"));
+
+        let options = parse(&["main.typ", "out.png", "--format", "SVG"]).unwrap();
+        assert_eq!(options.format, ExportFormat::Svg);
+        assert_eq!(options.output, Path::new("out.png"));
+    }
+
+    #[test]
+    fn parse_reads_options() {
+        let options = parse(&["-w", "main.typ", "--ppi", "300", "--input", "a=b=c", "--input", "d="]).unwrap();
+        assert!(options.watch);
+        assert_eq!(options.ppi, 300.0);
+        assert_eq!(options.inputs["a"], "b=c");
+        assert_eq!(options.inputs["d"], "");
+    }
+
+    #[test]
+    fn parse_rejects_invalid_arguments() {
+        let cases: [&[&str]; 8] = [
+            &[],
+            &["main.typ", "out.pdf", "extra.pdf"],
+            &["main.typ", "--unknown"],
+            &["main.typ", "--ppi"],
+            &["main.typ", "--ppi", "high"],
+            &["main.typ", "--format", "docx"],
+            &["main.typ", "--input", "novalue"],
+            &["main.typ", "--settings", "/nonexistent/settings.json"],
+        ];
+        for args in cases {
+            assert!(parse(args).is_err(), "{:?} was accepted", args);
+        }
+    }
+
+    #[test]
+    fn page_path_numbers_pages() {
+        let output = Path::new("out/page.png");
+        assert_eq!(page_path(output, 0, 1), Path::new("out/page.png"));
+        assert_eq!(page_path(output, 0, 3), Path::new("out/page-1.png"));
+        assert_eq!(page_path(output, 2, 3), Path::new("out/page-3.png"));
+        assert_eq!(page_path(Path::new("out/page"), 1, 2), Path::new("out/page-2"));
+        assert_eq!(page_path(Path::new("out/{p}.svg"), 1, 2), Path::new("out/2.svg"));
+        assert_eq!(page_path(Path::new("out/{p}.svg"), 0, 1), Path::new("out/1.svg"));
+    }
+
+    #[test]
+    fn exit_codes_distinguish_compile_and_usage_errors() {
+        assert_eq!(exit_code(&Ok(true)), EXIT_SUCCESS);
+        assert_eq!(exit_code(&Ok(false)), EXIT_COMPILE_ERROR);
+        assert_eq!(exit_code(&Err("Failed to read main.typ".to_string())), EXIT_USAGE_ERROR);
+    }
+}
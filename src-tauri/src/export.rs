@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::Serialize;
+use typst::diag::{Severity, SourceDiagnostic};
+use typst::foundations::Datetime;
+use typst::layout::PagedDocument;
+use typst::syntax::{Source, Span};
+use typst::World;
+
+use crate::lsp;
+use crate::outline::byte_to_position;
+use crate::packages::DownloadListener;
+use crate::settings::{CompileSettings, Settings};
+use crate::world::EditorWorld;
+
+/// Output formats supported by the exporters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Pdf,
+    Png,
+    Svg,
+}
+
+impl ExportFormat {
+    /// Guesses the format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Pdf => "pdf",
+            ExportFormat::Png => "png",
+            ExportFormat::Svg => "svg",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pdf" => Ok(ExportFormat::Pdf),
+            "png" => Ok(ExportFormat::Png),
+            "svg" => Ok(ExportFormat::Svg),
+            _ => Err(format!("Unknown export format: {}", s)),
+        }
+    }
+}
+
+/// Compiles `content` as the main file at `file_path` and exports it to PDF.
+pub fn compile_to_pdf(
+    content: &str,
+    file_path: Option<&str>,
+    settings: &Settings,
+    inputs: HashMap<String, String>,
+    listener: Option<DownloadListener>,
+) -> Result<Vec<u8>, String> {
+    let file_path = file_path.map(PathBuf::from);
+    let mut world = EditorWorld::new(content, file_path.as_ref(), settings).with_inputs(inputs);
+    if let Some(listener) = listener {
+        world = world.with_download_listener(listener);
+    }
+    world.update_source(content, file_path.as_ref());
+
+    let result = typst::compile::<PagedDocument>(&world);
+
+    match result.output {
+        Ok(document) => pdf(&document, &settings.compile),
+        Err(errors) => {
+            let error_msg = errors
+                .iter()
+                .map(|e| e.message.to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+            Err(error_msg)
+        }
+    }
+}
+
+/// Exports a document to PDF, stamping it with the pinned compile time if any.
+pub fn pdf(document: &PagedDocument, settings: &CompileSettings) -> Result<Vec<u8>, String> {
+    let options = typst_pdf::PdfOptions {
+        timestamp: settings.timestamp().and_then(pdf_timestamp),
+        ..Default::default()
+    };
+    typst_pdf::pdf(document, &options).map_err(|e| format!("PDF generation failed: {:?}", e))
+}
+
+/// Renders every page to a standalone SVG.
+pub fn svg_pages(document: &PagedDocument) -> Vec<String> {
+    document.pages.iter().map(typst_svg::svg).collect()
+}
+
+/// Renders every page to a PNG at `ppi` pixels per inch.
+pub fn png_pages(document: &PagedDocument, ppi: f32) -> Result<Vec<Vec<u8>>, String> {
+    document
+        .pages
+        .iter()
+        .map(|page| {
+            typst_render::render(page, ppi / 72.0)
+                .encode_png()
+                .map_err(|e| format!("PNG encoding failed: {}", e))
+        })
+        .collect()
+}
+
+/// A compiler error or warning with its location resolved, for clients that
+/// present diagnostics themselves.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompileDiagnostic {
+    /// The file the diagnostic points into, if any.
+    pub path: Option<String>,
+    /// LSP range: zero-based lines and UTF-16 columns.
+    pub range: Option<lsp::Range>,
+    /// LSP severity: 1 for errors, 2 for warnings.
+    pub severity: u8,
+    pub message: String,
+    pub hints: Vec<String>,
+}
+
+/// Resolves a diagnostic's location for [`CompileDiagnostic`].
+pub fn compile_diagnostic(world: &EditorWorld, diagnostic: &SourceDiagnostic) -> CompileDiagnostic {
+    let location = resolve_span(world, diagnostic.span);
+    CompileDiagnostic {
+        path: location.as_ref().map(|(path, _, _)| path.display().to_string()),
+        range: location.map(|(_, source, range)| lsp::Range {
+            start: byte_to_position(&source, range.start),
+            end: byte_to_position(&source, range.end),
+        }),
+        severity: match diagnostic.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+        },
+        message: diagnostic.message.to_string(),
+        hints: diagnostic.hints.iter().map(|hint| hint.to_string()).collect(),
+    }
+}
+
+/// The path of the file `span` points into, its source and the span's byte range.
+fn resolve_span(world: &EditorWorld, span: Span) -> Option<(PathBuf, Source, std::ops::Range<usize>)> {
+    let id = span.id()?;
+    let source = world.source(id).ok()?;
+    let range = source.range(span)?;
+    let path = world
+        .file_path(id)
+        .unwrap_or_else(|| id.vpath().as_rootless_path().to_path_buf());
+    Some((path, source, range))
+}
+
+/// Formats a diagnostic as `error: path:line:column: message` followed by its hints.
+pub fn format_diagnostic(world: &EditorWorld, diagnostic: &SourceDiagnostic) -> String {
+    let severity = match diagnostic.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+    };
+
+    let location = resolve_span(world, diagnostic.span).and_then(|(path, source, range)| {
+        let line = source.lines().byte_to_line(range.start)?;
+        let column = source.lines().byte_to_column(range.start)?;
+        Some(format!("{}:{}:{}", path.display(), line + 1, column + 1))
+    });
+
+    let mut output = match location {
+        Some(location) => format!("{}: {}: {}", severity, location, diagnostic.message),
+        None => format!("{}: {}", severity, diagnostic.message),
+    };
+    for hint in &diagnostic.hints {
+        output.push_str(&format!("\n  hint: {}", hint));
+    }
+    output
+}
+
+fn pdf_timestamp(time: DateTime<Utc>) -> Option<typst_pdf::Timestamp> {
+    let datetime = Datetime::from_ymd_hms(
+        time.year(),
+        time.month().try_into().ok()?,
+        time.day().try_into().ok()?,
+        time.hour().try_into().ok()?,
+        time.minute().try_into().ok()?,
+        time.second().try_into().ok()?,
+    )?;
+    Some(typst_pdf::Timestamp::new_utc(datetime))
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use typst::syntax::{highlight, LinkedNode, Source, Tag};
+
+use crate::lsp::{SemanticTokens, SemanticTokensEdit, SemanticTokensLegend};
+use crate::outline::byte_to_position;
+
+/// Prefix of native result ids, keeping them apart from the LSP server's ids.
+const RESULT_ID_PREFIX: &str = "native-";
+
+/// Token types of the native highlighter; a token's type is its index in this list.
+const TOKEN_TYPES: &[(Tag, &str)] = &[
+    (Tag::Comment, "comment"),
+    (Tag::Punctuation, "punctuation"),
+    (Tag::Escape, "escape"),
+    (Tag::Strong, "strong"),
+    (Tag::Emph, "emph"),
+    (Tag::Link, "link"),
+    (Tag::Raw, "raw"),
+    (Tag::Label, "label"),
+    (Tag::Ref, "ref"),
+    (Tag::Heading, "heading"),
+    (Tag::ListMarker, "marker"),
+    (Tag::ListTerm, "term"),
+    (Tag::MathDelimiter, "math-delimiter"),
+    (Tag::MathOperator, "math-operator"),
+    (Tag::Keyword, "keyword"),
+    (Tag::Operator, "operator"),
+    (Tag::Number, "number"),
+    (Tag::String, "string"),
+    (Tag::Function, "function"),
+    (Tag::Interpolated, "interpolated"),
+    (Tag::Error, "error"),
+];
+
+/// The native tokens never set modifier bits, so the legend has no modifiers.
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.iter().map(|(_, name)| name.to_string()).collect(),
+        token_modifiers: vec![],
+    }
+}
+
+/// Whether `result_id` was handed out by [`SemanticTokenCache`] rather than the LSP server.
+pub fn is_native_result_id(result_id: &str) -> bool {
+    result_id.starts_with(RESULT_ID_PREFIX)
+}
+
+fn token_type(tag: Tag) -> Option<u32> {
+    TOKEN_TYPES.iter().position(|(t, _)| *t == tag).map(|i| i as u32)
+}
+
+/// Classifies the syntax tree with `typst_syntax::highlight` and encodes the
+/// result as relative LSP semantic tokens. Leaves inherit the tag of their
+/// closest highlighted ancestor (e.g. the text inside `*strong*`), and tokens
+/// spanning several lines are split per line.
+pub fn semantic_tokens(source: &Source) -> Vec<u32> {
+    let mut tokens = Vec::new();
+    collect_tokens(source, &LinkedNode::new(source.root()), None, &mut tokens);
+
+    let mut data = Vec::with_capacity(tokens.len() * 5);
+    let (mut prev_line, mut prev_start) = (0, 0);
+    for (line, start, length, kind) in tokens {
+        let delta_line = line - prev_line;
+        let delta_start = if delta_line == 0 { start - prev_start } else { start };
+        data.extend([delta_line, delta_start, length, kind, 0]);
+        prev_line = line;
+        prev_start = start;
+    }
+    data
+}
+
+fn collect_tokens(source: &Source, node: &LinkedNode, inherited: Option<Tag>, tokens: &mut Vec<(u32, u32, u32, u32)>) {
+    let tag = highlight(node).or(inherited);
+
+    if node.children().next().is_some() {
+        for child in node.children() {
+            collect_tokens(source, &child, tag, tokens);
+        }
+        return;
+    }
+
+    let Some(kind) = tag.and_then(token_type) else {
+        return;
+    };
+
+    let range = node.range();
+    let mut offset = range.start;
+    for line in node.text().split_inclusive('\n') {
+        let text = line.trim_end_matches(['\n', '\r']);
+        let length = text.encode_utf16().count() as u32;
+        if length > 0 {
+            let position = byte_to_position(source, offset);
+            tokens.push((position.line as u32, position.character as u32, length, kind));
+        }
+        offset += line.len();
+    }
+}
+
+/// Remembers the last native token set per document so follow-up requests can
+/// be answered with a delta.
+#[derive(Default)]
+pub struct SemanticTokenCache {
+    next_id: u64,
+    entries: HashMap<String, (String, Vec<u32>)>,
+}
+
+impl SemanticTokenCache {
+    pub fn respond(&mut self, uri: String, data: Vec<u32>, previous_result_id: Option<String>) -> SemanticTokens {
+        self.next_id += 1;
+        let result_id = format!("{}{}", RESULT_ID_PREFIX, self.next_id);
+
+        let previous = self
+            .entries
+            .get(&uri)
+            .filter(|(id, _)| Some(id) == previous_result_id.as_ref())
+            .map(|(_, previous)| diff_tokens(previous, &data));
+
+        self.entries.insert(uri, (result_id.clone(), data.clone()));
+
+        match previous {
+            Some(edits) => SemanticTokens::Delta {
+                result_id: Some(result_id),
+                legend: legend(),
+                edits,
+            },
+            None => SemanticTokens::Full {
+                result_id: Some(result_id),
+                legend: legend(),
+                data,
+            },
+        }
+    }
+}
+
+/// Computes a single edit replacing the differing middle section of `old`.
+fn diff_tokens(old: &[u32], new: &[u32]) -> Vec<SemanticTokensEdit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    if prefix == old.len() && prefix == new.len() {
+        return vec![];
+    }
+
+    vec![SemanticTokensEdit {
+        start: prefix as u32,
+        delete_count: (old.len() - prefix - suffix) as u32,
+        data: new[prefix..new.len() - suffix].to_vec(),
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multi_line_tokens_are_split_per_line() {
+        let source = Source::detached("/* a\nbc */");
+        let comment = token_type(Tag::Comment).unwrap();
+        assert_eq!(semantic_tokens(&source), vec![0, 0, 4, comment, 0, 1, 0, 5, comment, 0]);
+    }
+
+    #[test]
+    fn token_columns_count_utf16_units() {
+        let source = Source::detached("😀 *b*");
+        let strong = token_type(Tag::Strong).unwrap();
+        // The emoji takes two UTF-16 units, so `*` starts at column 3
+        assert_eq!(semantic_tokens(&source)[..5], [0, 3, 1, strong, 0]);
+    }
+
+    #[test]
+    fn cache_answers_its_own_result_id_with_a_delta() {
+        let mut cache = SemanticTokenCache::default();
+        let SemanticTokens::Full { result_id, .. } = cache.respond("a".to_string(), vec![0, 0, 1, 0, 0], None) else {
+            panic!("first response must be the full set");
+        };
+        let result_id = result_id.unwrap();
+        assert!(is_native_result_id(&result_id));
+
+        let delta = cache.respond("a".to_string(), vec![0, 0, 2, 0, 0], Some(result_id));
+        assert!(matches!(delta, SemanticTokens::Delta { .. }));
+
+        // An id from the LSP server never matches a native one
+        let full = cache.respond("a".to_string(), vec![0, 0, 2, 0, 0], Some("2".to_string()));
+        assert!(matches!(full, SemanticTokens::Full { .. }));
+    }
+
+    #[test]
+    fn diff_tokens_of_equal_data_is_empty() {
+        assert!(diff_tokens(&[1, 2, 3], &[1, 2, 3]).is_empty());
+    }
+
+    #[test]
+    fn diff_tokens_replaces_the_changed_middle() {
+        let edits = diff_tokens(&[1, 2, 3, 4, 5], &[1, 2, 9, 9, 4, 5]);
+        assert_eq!(edits.len(), 1);
+        assert_eq!((edits[0].start, edits[0].delete_count), (2, 1));
+        assert_eq!(edits[0].data, vec![9, 9]);
+    }
+
+    #[test]
+    fn diff_tokens_appends_and_truncates() {
+        let appended = diff_tokens(&[1, 2], &[1, 2, 3]);
+        assert_eq!((appended[0].start, appended[0].delete_count), (2, 0));
+        assert_eq!(appended[0].data, vec![3]);
+
+        let truncated = diff_tokens(&[1, 2, 3], &[]);
+        assert_eq!((truncated[0].start, truncated[0].delete_count), (0, 3));
+        assert!(truncated[0].data.is_empty());
+    }
+}
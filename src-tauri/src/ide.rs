@@ -0,0 +1,180 @@
+use serde::Serialize;
+use typst::layout::{PagedDocument, Point};
+use typst::syntax::{FileId, Side, Source, Span};
+use typst::World;
+use typst_ide::{CompletionKind, Definition, IdeWorld, Jump, Tooltip};
+
+use crate::lsp::{
+    position_to_offset, CompletionItem, CompletionTextEdit, DefinitionLink, Hover, Position,
+    Range, TextEdit,
+};
+use crate::outline::byte_to_position;
+use crate::world::EditorWorld;
+
+impl IdeWorld for EditorWorld {
+    fn upcast(&self) -> &dyn World {
+        self
+    }
+}
+
+/// Where a click in the preview leads.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum JumpTarget {
+    File { uri: String, position: Position },
+    Url { url: String },
+    Position { page: usize, x: f64, y: f64 },
+}
+
+fn cursor(source: &Source, line: u64, character: u64) -> usize {
+    position_to_offset(source.text(), &Position { line, character })
+}
+
+/// Maps `typst-ide` completion kinds onto LSP `CompletionItemKind` values.
+fn completion_kind(kind: &CompletionKind) -> u64 {
+    match kind {
+        CompletionKind::Syntax => 15,
+        CompletionKind::Func => 3,
+        CompletionKind::Type => 7,
+        CompletionKind::Param => 6,
+        CompletionKind::Constant => 21,
+        CompletionKind::Symbol(_) => 21,
+        _ => 1,
+    }
+}
+
+pub fn completion(
+    world: &EditorWorld,
+    document: Option<&PagedDocument>,
+    line: u64,
+    character: u64,
+) -> Vec<CompletionItem> {
+    let source = world.snapshot_source();
+    let cursor = cursor(&source, line, character);
+
+    let Some((from, completions)) = typst_ide::autocomplete(world, document, &source, cursor, true)
+    else {
+        return vec![];
+    };
+
+    let range = Range {
+        start: byte_to_position(&source, from),
+        end: byte_to_position(&source, cursor),
+    };
+
+    completions
+        .into_iter()
+        .map(|completion| {
+            let new_text = completion.apply.as_ref().unwrap_or(&completion.label).to_string();
+            CompletionItem {
+                label: completion.label.to_string(),
+                insert_text: None,
+                insert_text_format: Some(if completion.apply.is_some() { 2 } else { 1 }),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range: range.clone(),
+                    new_text,
+                })),
+                additional_text_edits: vec![],
+                kind: Some(completion_kind(&completion.kind)),
+                detail: completion.detail.map(|detail| detail.to_string()),
+                documentation: None,
+                sort_text: None,
+                filter_text: None,
+                command: None,
+                data: None,
+            }
+        })
+        .collect()
+}
+
+pub fn hover(
+    world: &EditorWorld,
+    document: Option<&PagedDocument>,
+    line: u64,
+    character: u64,
+) -> Option<Hover> {
+    let source = world.snapshot_source();
+    let cursor = cursor(&source, line, character);
+
+    let value = match typst_ide::tooltip(world, document, &source, cursor, Side::Before)? {
+        Tooltip::Text(text) => text.to_string(),
+        Tooltip::Code(code) => format!("```typst\n{}\n```", code),
+    };
+
+    Some(Hover {
+        contents: serde_json::json!({ "kind": "markdown", "value": value }),
+        range: None,
+    })
+}
+
+pub fn definition(
+    world: &EditorWorld,
+    document: Option<&PagedDocument>,
+    uri: &str,
+    line: u64,
+    character: u64,
+) -> Vec<DefinitionLink> {
+    let source = world.snapshot_source();
+    let cursor = cursor(&source, line, character);
+
+    // Definitions in the standard library have no source location to jump to
+    match typst_ide::definition(world, document, &source, cursor, Side::Before) {
+        Some(Definition::Span(span)) => span_link(world, uri, span).into_iter().collect(),
+        _ => vec![],
+    }
+}
+
+fn span_link(world: &EditorWorld, main_uri: &str, span: Span) -> Option<DefinitionLink> {
+    let id = span.id()?;
+    let source = world.source(id).ok()?;
+    let range = source.range(span)?;
+    let range = Range {
+        start: byte_to_position(&source, range.start),
+        end: byte_to_position(&source, range.end),
+    };
+
+    Some(DefinitionLink {
+        uri: file_uri(world, main_uri, id)?,
+        range: range.clone(),
+        selection_range: range,
+        origin_selection_range: None,
+    })
+}
+
+fn file_uri(world: &EditorWorld, main_uri: &str, id: FileId) -> Option<String> {
+    if id == world.main() {
+        return Some(main_uri.to_string());
+    }
+    // `from_file_path` percent-encodes and needs an absolute path
+    let path = std::path::absolute(world.file_path(id)?).ok()?;
+    Some(url::Url::from_file_path(path).ok()?.to_string())
+}
+
+/// Resolves a click at `x`/`y` (in points) on page `page` of the preview.
+pub fn jump_from_click(
+    world: &EditorWorld,
+    document: &PagedDocument,
+    main_uri: &str,
+    page: usize,
+    x: f64,
+    y: f64,
+) -> Option<JumpTarget> {
+    let frame = &document.pages.get(page)?.frame;
+    let click = Point::new(typst::layout::Abs::pt(x), typst::layout::Abs::pt(y));
+
+    match typst_ide::jump_from_click(world, document, frame, click)? {
+        Jump::File(id, offset) => {
+            let source = world.source(id).ok()?;
+            Some(JumpTarget::File {
+                uri: file_uri(world, main_uri, id)?,
+                position: byte_to_position(&source, offset),
+            })
+        }
+        Jump::Url(url) => Some(JumpTarget::Url { url: url.as_str().to_string() }),
+        Jump::Position(position) => Some(JumpTarget::Position {
+            page: position.page.get() - 1,
+            x: position.point.x.to_pt(),
+            y: position.point.y.to_pt(),
+        }),
+    }
+}
@@ -1,5 +1,14 @@
-mod world;
+pub mod world;
+pub mod export;
+mod highlight;
+mod ide;
 mod lsp;
+mod outline;
+mod package_index;
+pub mod packages;
+mod preview_server;
+mod remote;
+pub mod settings;
 
 use std::collections::{HashMap, hash_map::DefaultHasher};
 use std::hash::{Hash, Hasher};
@@ -7,21 +16,45 @@ use std::sync::Arc;
 
 use serde::Serialize;
 use std::sync::Mutex;
-use tauri::{AppHandle, Emitter};
+use tauri::{AppHandle, Emitter, Manager, RunEvent};
 use tracing_subscriber;
 use typst::layout::{Frame, FrameItem, PagedDocument, Point, Size};
+use typst::syntax::package::PackageSpec;
 use typst::syntax::Span;
-use typst_pdf;
 
-use lsp::{CompletionItem, Hover, Location, LspManager};
+use lsp::{
+    CodeAction, CompletionItem, DefinitionLink, Diagnostic, FormattingOptions, Hover, InlayHint,
+    Location, LspManager, LspStatus, PrepareRename, Range, SemanticTokens, ServerInfo,
+    SignatureHelp, TextDocumentContentChange, TextEdit, WorkspaceEdit,
+};
+use highlight::SemanticTokenCache;
+use ide::JumpTarget;
+use outline::OutlineItem;
+use package_index::{PackageIndex, PackageSummary};
+use packages::{CachedPackage, DownloadListener, PackageFetcher};
+use preview_server::PreviewServer;
+use remote::{RemoteInfo, RemoteServer};
+use settings::{CompileSettings, FormatSettings, LspSettings, PackageSettings, RemoteSettings, Settings};
+use export::CompileDiagnostic;
 use world::EditorWorld;
 
 struct AppState {
     world: Mutex<Option<EditorWorld>>,
+    last_document: Mutex<Option<PagedDocument>>,
     last_hashes: Mutex<Vec<u64>>,
     last_blocks: Mutex<Vec<HashMap<String, u64>>>,
     lsp_manager: Arc<LspManager>,
     current_file_path: Mutex<Option<std::path::PathBuf>>,
+    settings: Mutex<Settings>,
+    semantic_tokens: Mutex<SemanticTokenCache>,
+    app_handle: std::sync::OnceLock<AppHandle>,
+    package_fetcher: std::sync::OnceLock<Arc<PackageFetcher>>,
+    last_revision: Mutex<u64>,
+    package_index: Mutex<Option<Arc<PackageIndex>>>,
+    /// `sys.inputs` set for this session, overriding the project's inputs file.
+    inputs: Mutex<HashMap<String, String>>,
+    preview_server: Mutex<Option<Arc<PreviewServer>>>,
+    remote_server: Mutex<Option<Arc<RemoteServer>>>,
 }
 
 #[derive(Serialize, Clone)]
@@ -93,14 +126,40 @@ async fn compile_typst(
         let mut current_path = state.current_file_path.lock().map_err(|e| e.to_string())?;
         *current_path = Some(std::path::PathBuf::from(path));
     }
-    
+
+    *state.last_revision.lock().map_err(|e| e.to_string())? = revision;
+
     // Run compilation in blocking thread to avoid blocking async runtime
-    let result = tokio::task::spawn_blocking(move || {
-        build_patch_internal(&content, &state)
+    let compile_state = Arc::clone(&state);
+    let (result, diagnostics) = tokio::task::spawn_blocking(move || {
+        let mut diagnostics = Vec::new();
+        let result = build_patch_internal(&content, &compile_state, &mut diagnostics);
+        (result, diagnostics)
     })
     .await
     .map_err(|e| e.to_string())?;
 
+    emit_compile_result(&app, &state, revision, result, &diagnostics)
+}
+
+fn emit_compile_result(
+    app: &AppHandle,
+    state: &AppState,
+    revision: u64,
+    result: Result<(Vec<PagePatch>, usize), String>,
+    diagnostics: &[CompileDiagnostic],
+) -> Result<(), String> {
+    // Browsers watching the preview server get the same patches as the editor
+    if let Some(server) = state.preview_server.lock().map_err(|e| e.to_string())?.as_ref() {
+        match &result {
+            Ok((pages, total_pages)) => server.publish(revision, pages, *total_pages),
+            Err(message) => server.publish_error(revision, message),
+        }
+    }
+    if let Some(server) = state.remote_server.lock().map_err(|e| e.to_string())?.as_ref() {
+        server.publish_diagnostics(revision, &result, diagnostics);
+    }
+
     match result {
         Ok((pages, total_pages)) => {
             let payload = CompileResult { revision, pages, total_pages };
@@ -117,6 +176,22 @@ async fn compile_typst(
     Ok(())
 }
 
+/// Recompiles the current source, e.g. once a background package download finishes.
+fn recompile_current(app: &AppHandle, state: &AppState) -> Result<(), String> {
+    let content = {
+        let world = state.world.lock().map_err(|e| e.to_string())?;
+        match world.as_ref() {
+            Some(world) => world.snapshot_source().text().to_string(),
+            None => return Ok(()),
+        }
+    };
+    let revision = *state.last_revision.lock().map_err(|e| e.to_string())?;
+
+    let mut diagnostics = Vec::new();
+    let result = build_patch_internal(&content, state, &mut diagnostics);
+    emit_compile_result(app, state, revision, result, &diagnostics)
+}
+
 /// Tauri command: Get initial compilation result (for cold start)
 #[tauri::command]
 fn compile_and_get(
@@ -126,6 +201,23 @@ fn compile_and_get(
     build_patch(&content, &state)
 }
 
+/// Runs `f` with the compiler world and last compiled document on a blocking
+/// thread; used for the built-in language features when Tinymist is unavailable.
+async fn with_world<T, F>(state: &Arc<AppState>, f: F) -> Result<Option<T>, String>
+where
+    T: Send + 'static,
+    F: FnOnce(&EditorWorld, Option<&PagedDocument>) -> T + Send + 'static,
+{
+    let state = Arc::clone(state);
+    tokio::task::spawn_blocking(move || {
+        let world = state.world.lock().map_err(|e| e.to_string())?;
+        let document = state.last_document.lock().map_err(|e| e.to_string())?;
+        Ok(world.as_ref().map(|world| f(world, document.as_ref())))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
 /// Tauri command: Get completion items at position
 #[tauri::command]
 async fn lsp_completion(
@@ -136,7 +228,27 @@ async fn lsp_completion(
     state: tauri::State<'_, Arc<AppState>>,
 ) -> Result<Vec<CompletionItem>, String> {
     let manager = Arc::clone(&state.lsp_manager);
-    manager.get_completion(uri, line, character, version).await
+    match manager.get_completion(uri, line, character, version).await {
+        Ok(items) => Ok(items),
+        Err(e) => {
+            tracing::debug!("Using built-in completion: {}", e);
+            let items = with_world(&state, move |world, document| {
+                ide::completion(world, document, line, character)
+            })
+            .await?;
+            Ok(items.unwrap_or_default())
+        }
+    }
+}
+
+/// Tauri command: Resolve documentation and edits for a completion item
+#[tauri::command]
+async fn lsp_resolve_completion(
+    item: CompletionItem,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<CompletionItem, String> {
+    let manager = Arc::clone(&state.lsp_manager);
+    manager.resolve_completion(item).await
 }
 
 /// Tauri command: Get hover information at position
@@ -149,10 +261,20 @@ async fn lsp_hover(
     state: tauri::State<'_, Arc<AppState>>,
 ) -> Result<Option<Hover>, String> {
     let manager = Arc::clone(&state.lsp_manager);
-    manager.get_hover(uri, line, character, version).await
+    match manager.get_hover(uri, line, character, version).await {
+        Ok(hover) => Ok(hover),
+        Err(e) => {
+            tracing::debug!("Using built-in hover: {}", e);
+            let hover = with_world(&state, move |world, document| {
+                ide::hover(world, document, line, character)
+            })
+            .await?;
+            Ok(hover.flatten())
+        }
+    }
 }
 
-/// Tauri command: Go to definition at position
+/// Tauri command: Go to definition at position, returning every candidate target
 #[tauri::command]
 async fn lsp_goto_definition(
     uri: String,
@@ -160,21 +282,685 @@ async fn lsp_goto_definition(
     character: u64,
     version: i64,
     state: tauri::State<'_, Arc<AppState>>,
-) -> Result<Option<Location>, String> {
+) -> Result<Vec<DefinitionLink>, String> {
+    let manager = Arc::clone(&state.lsp_manager);
+    match manager.goto_definition(uri.clone(), line, character, version).await {
+        Ok(links) => Ok(links),
+        Err(e) => {
+            tracing::debug!("Using built-in definition: {}", e);
+            let links = with_world(&state, move |world, document| {
+                ide::definition(world, document, &uri, line, character)
+            })
+            .await?;
+            Ok(links.unwrap_or_default())
+        }
+    }
+}
+
+/// Tauri command: Resolve a click at `x`/`y` (pt) on a preview page to a source
+/// position, URL or other document position
+#[tauri::command]
+async fn jump_from_click(
+    uri: String,
+    page: usize,
+    x: f64,
+    y: f64,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Option<JumpTarget>, String> {
+    let target = with_world(&state, move |world, document| {
+        ide::jump_from_click(world, document?, &uri, page, x, y)
+    })
+    .await?;
+    Ok(target.flatten())
+}
+
+/// Tauri command: Go to declaration at position
+#[tauri::command]
+async fn lsp_goto_declaration(
+    uri: String,
+    line: u64,
+    character: u64,
+    version: i64,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Vec<DefinitionLink>, String> {
+    let manager = Arc::clone(&state.lsp_manager);
+    manager.goto_declaration(uri, line, character, version).await
+}
+
+/// Tauri command: Go to type definition at position
+#[tauri::command]
+async fn lsp_goto_type_definition(
+    uri: String,
+    line: u64,
+    character: u64,
+    version: i64,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Vec<DefinitionLink>, String> {
+    let manager = Arc::clone(&state.lsp_manager);
+    manager.goto_type_definition(uri, line, character, version).await
+}
+
+/// Tauri command: Get signature help for the call at position
+#[tauri::command]
+async fn lsp_signature_help(
+    uri: String,
+    line: u64,
+    character: u64,
+    version: i64,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Option<SignatureHelp>, String> {
+    let manager = Arc::clone(&state.lsp_manager);
+    manager.signature_help(uri, line, character, version).await
+}
+
+/// Tauri command: Get inlay hints (e.g. parameter names) within a range
+#[tauri::command]
+async fn lsp_inlay_hints(
+    uri: String,
+    range: Range,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Vec<InlayHint>, String> {
+    let manager = Arc::clone(&state.lsp_manager);
+    manager.inlay_hints(uri, range).await
+}
+
+/// Tauri command: Find all references to the symbol at position
+#[tauri::command]
+async fn lsp_references(
+    uri: String,
+    line: u64,
+    character: u64,
+    version: i64,
+    include_declaration: Option<bool>,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Vec<Location>, String> {
+    let manager = Arc::clone(&state.lsp_manager);
+    manager
+        .find_references(uri, line, character, version, include_declaration.unwrap_or(true))
+        .await
+}
+
+/// Tauri command: Check whether the symbol at position can be renamed
+#[tauri::command]
+async fn lsp_prepare_rename(
+    uri: String,
+    line: u64,
+    character: u64,
+    version: i64,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Option<PrepareRename>, String> {
+    let manager = Arc::clone(&state.lsp_manager);
+    manager.prepare_rename(uri, line, character, version).await
+}
+
+/// Tauri command: Rename the symbol at position across the workspace
+#[tauri::command]
+async fn lsp_rename(
+    uri: String,
+    line: u64,
+    character: u64,
+    version: i64,
+    new_name: String,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Option<WorkspaceEdit>, String> {
     let manager = Arc::clone(&state.lsp_manager);
-    manager.goto_definition(uri, line, character, version).await
+    manager.rename(uri, line, character, version, new_name).await
+}
+
+/// Tauri command: Get code actions (quick fixes, refactorings) for a range
+#[tauri::command]
+async fn lsp_code_actions(
+    uri: String,
+    range: Range,
+    diagnostics: Option<Vec<Diagnostic>>,
+    only: Option<Vec<String>>,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Vec<CodeAction>, String> {
+    let manager = Arc::clone(&state.lsp_manager);
+    manager
+        .code_actions(uri, range, diagnostics.unwrap_or_default(), only)
+        .await
+}
+
+/// Tauri command: Run a command-style code action on the LSP server
+#[tauri::command]
+async fn lsp_execute_command(
+    command: String,
+    arguments: Option<Vec<serde_json::Value>>,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<serde_json::Value, String> {
+    let manager = Arc::clone(&state.lsp_manager);
+    manager
+        .execute_command(command, arguments.unwrap_or_default())
+        .await
+}
+
+/// Tauri command: Format a document (or `range` of it) through the LSP server
+///
+/// Indentation falls back to the saved format settings when not given.
+#[tauri::command]
+async fn lsp_format(
+    uri: String,
+    range: Option<Range>,
+    tab_size: Option<u32>,
+    insert_spaces: Option<bool>,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Vec<TextEdit>, String> {
+    let format = state.settings.lock().map_err(|e| e.to_string())?.format.clone();
+    let options = FormattingOptions {
+        tab_size: tab_size.unwrap_or(format.tab_size),
+        insert_spaces: insert_spaces.unwrap_or(format.insert_spaces),
+    };
+
+    let manager = Arc::clone(&state.lsp_manager);
+    manager.format(uri, options, range).await
+}
+
+/// Tauri command: Save a document to disk, formatting it first when format-on-save is enabled
+///
+/// `version` is the editor's version of `content` for the LSP document `uri`.
+/// Returns the content that was written so the editor can pick up formatting changes.
+#[tauri::command]
+async fn save_document(
+    path: String,
+    content: String,
+    uri: Option<String>,
+    version: Option<i64>,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<String, String> {
+    let format = state.settings.lock().map_err(|e| e.to_string())?.format.clone();
+
+    let content = match (uri, version) {
+        (Some(uri), Some(version)) if format.on_save => {
+            let options = FormattingOptions {
+                tab_size: format.tab_size,
+                insert_spaces: format.insert_spaces,
+            };
+            let manager = Arc::clone(&state.lsp_manager);
+            // The edits must be computed against the text being saved
+            let formatted = match manager.sync_text(uri.clone(), content.clone(), version).await {
+                Ok(()) => manager.format(uri, options, None).await,
+                Err(e) => Err(e),
+            };
+            match formatted {
+                Ok(edits) => lsp::apply_text_edits(&content, &edits),
+                Err(e) => {
+                    tracing::warn!("Format on save skipped: {}", e);
+                    content
+                }
+            }
+        }
+        _ => content,
+    };
+
+    std::fs::write(&path, &content).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    Ok(content)
+}
+
+/// Tauri command: Get the outline (headings, `#let` definitions, labels) of a document
+///
+/// Uses `textDocument/documentSymbol` when the LSP server answers, otherwise walks the
+/// syntax tree of `content` or of the source currently held by the compiler.
+#[tauri::command]
+async fn document_outline(
+    uri: String,
+    content: Option<String>,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Vec<OutlineItem>, String> {
+    let manager = Arc::clone(&state.lsp_manager);
+    match manager.document_symbols(uri).await {
+        Ok(symbols) if !symbols.is_empty() => {
+            return Ok(symbols.into_iter().map(OutlineItem::from).collect());
+        }
+        Ok(_) => {}
+        Err(e) => tracing::debug!("Outline falling back to syntax tree: {}", e),
+    }
+
+    let source = match content {
+        Some(content) => typst::syntax::Source::detached(content),
+        None => {
+            let world = state.world.lock().map_err(|e| e.to_string())?;
+            match world.as_ref() {
+                Some(world) => world.snapshot_source(),
+                None => return Ok(vec![]),
+            }
+        }
+    };
+
+    Ok(outline::outline_from_source(&source))
+}
+
+/// Tauri command: Get semantic tokens for editor highlighting
+///
+/// Served by the LSP server when it supports semantic tokens, otherwise computed
+/// natively from the syntax tree. Passing the last `resultId` as
+/// `previous_result_id` returns a delta instead of the full token set.
+#[tauri::command]
+async fn semantic_tokens(
+    uri: String,
+    content: Option<String>,
+    previous_result_id: Option<String>,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Option<SemanticTokens>, String> {
+    // Each source only gets a delta against a result id it handed out itself
+    let (native_previous, lsp_previous) = match previous_result_id {
+        Some(id) if highlight::is_native_result_id(&id) => (Some(id), None),
+        other => (None, other),
+    };
+
+    let manager = Arc::clone(&state.lsp_manager);
+    if manager.ensure_started().await.is_ok() && manager.supports_semantic_tokens().await {
+        if let Some(tokens) = manager.semantic_tokens(uri.clone(), lsp_previous).await? {
+            return Ok(Some(tokens));
+        }
+    }
+
+    let source = match content {
+        Some(content) => typst::syntax::Source::detached(content),
+        None => {
+            let world = state.world.lock().map_err(|e| e.to_string())?;
+            match world.as_ref() {
+                Some(world) => world.snapshot_source(),
+                None => return Ok(None),
+            }
+        }
+    };
+
+    let data = highlight::semantic_tokens(&source);
+    let mut cache = state.semantic_tokens.lock().map_err(|e| e.to_string())?;
+    Ok(Some(cache.respond(uri, data, native_previous)))
 }
 
 /// Tauri command: Update document content in LSP server
+///
+/// `changes` carries range edits; the full `content` is only required when
+/// there are none or the document is not open yet.
 #[tauri::command]
 async fn lsp_update_document(
     uri: String,
-    content: String,
+    content: Option<String>,
     version: i64,
+    changes: Option<Vec<TextDocumentContentChange>>,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<(), String> {
+    let manager = Arc::clone(&state.lsp_manager);
+    manager.update_document(uri, content, version, changes).await
+}
+
+/// Tauri command: Get the current LSP server status (also pushed via `lsp-status` events)
+#[tauri::command]
+async fn lsp_status(state: tauri::State<'_, Arc<AppState>>) -> Result<LspStatus, String> {
+    Ok(state.lsp_manager.status().await)
+}
+
+/// Tauri command: Get the persisted editor settings
+#[tauri::command]
+fn get_settings(state: tauri::State<'_, Arc<AppState>>) -> Result<Settings, String> {
+    let settings = state.settings.lock().map_err(|e| e.to_string())?;
+    Ok(settings.clone())
+}
+
+/// Tauri command: Get the `sys.inputs` set for this session
+#[tauri::command]
+fn get_inputs(state: tauri::State<'_, Arc<AppState>>) -> Result<HashMap<String, String>, String> {
+    let inputs = state.inputs.lock().map_err(|e| e.to_string())?;
+    Ok(inputs.clone())
+}
+
+/// Tauri command: Set the `sys.inputs` for this session; applied on the next compile
+#[tauri::command]
+fn set_inputs(
+    inputs: HashMap<String, String>,
     state: tauri::State<'_, Arc<AppState>>,
 ) -> Result<(), String> {
+    *state.inputs.lock().map_err(|e| e.to_string())? = inputs;
+    Ok(())
+}
+
+/// The `sys.inputs` for compiling `file_path`: the project's inputs file
+/// overlaid with the session inputs.
+fn resolve_inputs(
+    state: &AppState,
+    file_path: Option<&std::path::Path>,
+) -> Result<HashMap<String, String>, String> {
+    let mut inputs = file_path
+        .and_then(|path| path.parent())
+        .map(settings::load_project_inputs)
+        .unwrap_or_default();
+    inputs.extend(state.inputs.lock().map_err(|e| e.to_string())?.clone());
+    Ok(inputs)
+}
+
+/// Tauri command: Try to start the language server again after it failed to start
+#[tauri::command]
+async fn lsp_retry(state: tauri::State<'_, Arc<AppState>>) -> Result<(), String> {
     let manager = Arc::clone(&state.lsp_manager);
-    manager.update_document(uri, content, version).await
+    manager.retry().await
+}
+
+/// Tauri command: Save language server settings and restart the server with them
+#[tauri::command]
+async fn set_lsp_settings(
+    lsp: LspSettings,
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<(), String> {
+    {
+        let mut settings = state.settings.lock().map_err(|e| e.to_string())?;
+        settings.lsp = lsp.clone();
+        settings.save(&Settings::path(&app)?)?;
+    }
+    // A running server keeps its command line, so stop it; the next request
+    // starts one with the new settings
+    state.lsp_manager.set_settings(lsp).await;
+    state.lsp_manager.stop().await;
+    Ok(())
+}
+
+/// Tauri command: Save formatting settings
+#[tauri::command]
+fn set_format_settings(
+    format: FormatSettings,
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<(), String> {
+    let mut settings = state.settings.lock().map_err(|e| e.to_string())?;
+    settings.format = format;
+    settings.save(&Settings::path(&app)?)
+}
+
+/// Tauri command: Save package settings and rebuild the compiler world with them
+#[tauri::command]
+fn set_package_settings(
+    packages: PackageSettings,
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<(), String> {
+    {
+        let mut settings = state.settings.lock().map_err(|e| e.to_string())?;
+        settings.packages = packages;
+        settings.save(&Settings::path(&app)?)?;
+    }
+
+    // The index source may have changed
+    *state.package_index.lock().map_err(|e| e.to_string())? = None;
+
+    // The world is recreated with the new settings on the next compile
+    let mut world = state.world.lock().map_err(|e| e.to_string())?;
+    *world = None;
+    Ok(())
+}
+
+/// Tauri command: Save compile settings and rebuild the compiler world with them
+#[tauri::command]
+fn set_compile_settings(
+    compile: CompileSettings,
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<(), String> {
+    {
+        let mut settings = state.settings.lock().map_err(|e| e.to_string())?;
+        settings.compile = compile;
+        settings.save(&Settings::path(&app)?)?;
+    }
+
+    let mut world = state.world.lock().map_err(|e| e.to_string())?;
+    *world = None;
+    Ok(())
+}
+
+/// Tauri command: List packages in the local package data and cache directories
+#[tauri::command]
+async fn list_cached_packages(
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Vec<CachedPackage>, String> {
+    let settings = state.settings.lock().map_err(|e| e.to_string())?.packages.clone();
+    tokio::task::spawn_blocking(move || {
+        packages::list_cached(&packages::new_storage(&settings), &settings)
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Tauri command: Delete a downloaded package from the cache directory
+#[tauri::command]
+async fn delete_cached_package(
+    namespace: String,
+    name: String,
+    version: String,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<(), String> {
+    let settings = state.settings.lock().map_err(|e| e.to_string())?.packages.clone();
+    tokio::task::spawn_blocking(move || {
+        packages::delete_cached(&packages::new_storage(&settings), &namespace, &name, &version)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    // Drop sources cached from the deleted package
+    let mut world = state.world.lock().map_err(|e| e.to_string())?;
+    *world = None;
+    Ok(())
+}
+
+/// Tauri command: Create a project from a template package such as
+/// `@preview/charged-ieee:0.1.0` and return the path of its entrypoint
+#[tauri::command]
+async fn create_project_from_template(
+    spec: String,
+    directory: String,
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<String, String> {
+    let spec: PackageSpec = spec.parse().map_err(|e| format!("Invalid package spec: {}", e))?;
+    let settings = state.settings.lock().map_err(|e| e.to_string())?.packages.clone();
+    let listener = download_listener(app);
+
+    let entrypoint = tokio::task::spawn_blocking(move || {
+        packages::create_from_template(
+            &packages::new_storage(&settings),
+            &settings,
+            &spec,
+            std::path::Path::new(&directory),
+            Some(&listener),
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    Ok(entrypoint.to_string_lossy().to_string())
+}
+
+/// Returns the package index, loading it from the on-disk cache or the
+/// configured source on first use or when `force` is set.
+async fn load_package_index(
+    app: &AppHandle,
+    state: &AppState,
+    force: bool,
+) -> Result<Arc<PackageIndex>, String> {
+    if !force {
+        if let Some(index) = state.package_index.lock().map_err(|e| e.to_string())?.as_ref() {
+            return Ok(Arc::clone(index));
+        }
+    }
+
+    let source = state
+        .settings
+        .lock()
+        .map_err(|e| e.to_string())?
+        .packages
+        .index_url
+        .clone()
+        .unwrap_or_else(package_index::default_index_url);
+    let cache_file = app
+        .path()
+        .app_cache_dir()
+        .map_err(|e| format!("Failed to resolve cache directory: {}", e))?
+        .join(package_index::cache_file_name(&source));
+
+    let index = tokio::task::spawn_blocking(move || PackageIndex::load(&source, &cache_file, force))
+        .await
+        .map_err(|e| e.to_string())??;
+    let index = Arc::new(index);
+    *state.package_index.lock().map_err(|e| e.to_string())? = Some(Arc::clone(&index));
+    Ok(index)
+}
+
+/// Tauri command: Refetch the package index and return its categories
+#[tauri::command]
+async fn refresh_package_index(
+    force: Option<bool>,
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Vec<String>, String> {
+    let index = load_package_index(&app, &state, force.unwrap_or(true)).await?;
+    Ok(index.categories())
+}
+
+/// Tauri command: Search the package index by name, keyword or description
+#[tauri::command]
+async fn search_packages(
+    query: String,
+    category: Option<String>,
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Vec<PackageSummary>, String> {
+    let index = load_package_index(&app, &state, false).await?;
+    Ok(index.search(&query, category.as_deref()))
+}
+
+/// Tauri command: Get all versions and details of one package from the index
+#[tauri::command]
+async fn get_package_info(
+    name: String,
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Option<PackageSummary>, String> {
+    let index = load_package_index(&app, &state, false).await?;
+    Ok(index.get(&name).cloned())
+}
+
+/// Tauri command: Start the browser preview server and return its URL
+///
+/// Listens on localhost only unless `lan` is set.
+#[tauri::command]
+async fn start_preview_server(
+    port: Option<u16>,
+    lan: Option<bool>,
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<String, String> {
+    if let Some(old) = state.preview_server.lock().map_err(|e| e.to_string())?.take() {
+        old.stop();
+    }
+
+    let server = PreviewServer::start(
+        port.unwrap_or(preview_server::DEFAULT_PORT),
+        lan.unwrap_or(false),
+    )
+    .await?;
+    let url = server.url().to_string();
+    *state.preview_server.lock().map_err(|e| e.to_string())? = Some(server);
+
+    // Patches only carry changed blocks, so forget the previous output and
+    // recompile to give the server the whole document
+    state.last_hashes.lock().map_err(|e| e.to_string())?.clear();
+    state.last_blocks.lock().map_err(|e| e.to_string())?.clear();
+    let state = Arc::clone(&state);
+    tokio::task::spawn_blocking(move || recompile_current(&app, &state))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    Ok(url)
+}
+
+/// Tauri command: Retry packages whose download failed and recompile
+///
+/// Failed downloads are otherwise only retried after a backoff.
+#[tauri::command]
+async fn retry_package_downloads(
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<(), String> {
+    if let Some(fetcher) = state.package_fetcher.get() {
+        fetcher.clear_failures();
+    }
+    let state = Arc::clone(&state);
+    tokio::task::spawn_blocking(move || recompile_current(&app, &state))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Tauri command: Stop the browser preview server
+#[tauri::command]
+fn stop_preview_server(state: tauri::State<'_, Arc<AppState>>) -> Result<(), String> {
+    if let Some(server) = state.preview_server.lock().map_err(|e| e.to_string())?.take() {
+        server.stop();
+    }
+    Ok(())
+}
+
+/// Tauri command: Get the URL of the running browser preview server, if any
+#[tauri::command]
+fn preview_server_url(state: tauri::State<'_, Arc<AppState>>) -> Result<Option<String>, String> {
+    let server = state.preview_server.lock().map_err(|e| e.to_string())?;
+    Ok(server.as_ref().map(|server| server.url().to_string()))
+}
+
+/// Stops the remote control server and starts it again if `remote` enables it.
+async fn restart_remote_server(
+    app: AppHandle,
+    state: &AppState,
+    remote: &RemoteSettings,
+) -> Result<Option<RemoteInfo>, String> {
+    if let Some(old) = state.remote_server.lock().map_err(|e| e.to_string())?.take() {
+        old.stop();
+    }
+    if !remote.enabled {
+        return Ok(None);
+    }
+
+    let server = RemoteServer::start(app, remote.port).await?;
+    let info = server.info().clone();
+    *state.remote_server.lock().map_err(|e| e.to_string())? = Some(server);
+    Ok(Some(info))
+}
+
+/// Tauri command: Save remote control settings and start or stop the server
+#[tauri::command]
+async fn set_remote_settings(
+    remote: RemoteSettings,
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<Option<RemoteInfo>, String> {
+    {
+        let mut settings = state.settings.lock().map_err(|e| e.to_string())?;
+        settings.remote = remote.clone();
+        settings.save(&Settings::path(&app)?)?;
+    }
+    restart_remote_server(app, &state, &remote).await
+}
+
+/// Tauri command: Get the port and token of the running remote control server
+#[tauri::command]
+fn remote_control_info(state: tauri::State<'_, Arc<AppState>>) -> Result<Option<RemoteInfo>, String> {
+    let server = state.remote_server.lock().map_err(|e| e.to_string())?;
+    Ok(server.as_ref().map(|server| server.info().clone()))
+}
+
+/// Tauri command: Check a language server binary and report its version
+///
+/// Validates `lsp` when given, otherwise the currently saved settings.
+#[tauri::command]
+async fn lsp_validate_server(
+    lsp: Option<LspSettings>,
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<ServerInfo, String> {
+    let lsp = match lsp {
+        Some(lsp) => lsp,
+        None => state.settings.lock().map_err(|e| e.to_string())?.lsp.clone(),
+    };
+    LspManager::validate_server(&lsp).await
 }
 
 fn hash_svg(svg: &str) -> u64 {
@@ -297,17 +1083,39 @@ fn block_from_items(
     }
 }
 
-fn build_patch_internal(content: &str, state: &AppState) -> Result<(Vec<PagePatch>, usize), String> {
+/// Compiles `content` and diffs the pages against the last compile. The errors
+/// and warnings, with resolved locations, are added to `diagnostics`.
+fn build_patch_internal(
+    content: &str,
+    state: &AppState,
+    diagnostics: &mut Vec<CompileDiagnostic>,
+) -> Result<(Vec<PagePatch>, usize), String> {
     let mut world_guard = state.world.lock().map_err(|e| e.to_string())?;
     let current_path = state.current_file_path.lock().map_err(|e| e.to_string())?;
+    let settings = state.settings.lock().map_err(|e| e.to_string())?.clone();
+    let inputs = resolve_inputs(state, current_path.as_deref())?;
 
     let world = world_guard.get_or_insert_with(|| {
-        EditorWorld::new(content, current_path.as_ref())
+        let mut world = EditorWorld::new(content, current_path.as_ref(), &settings);
+        if let Some(app) = state.app_handle.get() {
+            world = world.with_download_listener(download_listener(app.clone()));
+        }
+        if let Some(fetcher) = state.package_fetcher.get() {
+            world = world.with_package_fetcher(Arc::clone(fetcher));
+        }
+        world
     });
+    world.set_inputs(inputs);
     world.update_source(content, current_path.as_ref());
     let source_snapshot = world.snapshot_source();
 
     let result = typst::compile::<PagedDocument>(world);
+    let errors = result.output.as_ref().err().into_iter().flatten();
+    diagnostics.extend(
+        errors
+            .chain(&result.warnings)
+            .map(|diagnostic| export::compile_diagnostic(world, diagnostic)),
+    );
 
     match result.output {
         Ok(document) => {
@@ -419,6 +1227,9 @@ fn build_patch_internal(content: &str, state: &AppState) -> Result<(Vec<PagePatc
                 }
             }
 
+            let mut last_document = state.last_document.lock().map_err(|e| e.to_string())?;
+            *last_document = Some(document);
+
             Ok((patches, total_pages))
         }
         Err(errors) => {
@@ -432,8 +1243,17 @@ fn build_patch_internal(content: &str, state: &AppState) -> Result<(Vec<PagePatc
     }
 }
 
+/// Emits package download progress as `package-download` events.
+fn download_listener(app: AppHandle) -> DownloadListener {
+    Arc::new(move |event| {
+        if let Err(e) = app.emit("package-download", event) {
+            tracing::warn!("Failed to emit package-download event: {}", e);
+        }
+    })
+}
+
 fn build_patch(content: &str, state: &AppState) -> Result<Vec<PagePatch>, String> {
-    build_patch_internal(content, state).map(|(patches, _)| patches)
+    build_patch_internal(content, state, &mut Vec::new()).map(|(patches, _)| patches)
 }
 
 /// Tauri command: Export Typst content to PDF
@@ -442,8 +1262,12 @@ async fn export_pdf(
     content: String,
     file_path: Option<String>,
     app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
 ) -> Result<String, String> {
     use tauri_plugin_dialog::DialogExt;
+
+    let settings = state.settings.lock().map_err(|e| e.to_string())?.clone();
+    let inputs = resolve_inputs(&state, file_path.as_deref().map(std::path::Path::new))?;
     
     // Get the save path from user
     let save_path = app.dialog()
@@ -458,8 +1282,9 @@ async fn export_pdf(
     };
     
     // Compile to PDF in blocking thread
+    let listener = download_listener(app.clone());
     let result = tokio::task::spawn_blocking(move || {
-        compile_to_pdf(&content, file_path.as_deref())
+        export::compile_to_pdf(&content, file_path.as_deref(), &settings, inputs, Some(listener))
     })
     .await
     .map_err(|e| e.to_string())?;
@@ -475,32 +1300,6 @@ async fn export_pdf(
     }
 }
 
-fn compile_to_pdf(content: &str, file_path: Option<&str>) -> Result<Vec<u8>, String> {
-    use std::path::PathBuf;
-    
-    let file_path = file_path.map(PathBuf::from);
-    let world = EditorWorld::new(content, file_path.as_ref());
-    world.update_source(content, file_path.as_ref());
-    
-    let result = typst::compile::<PagedDocument>(&world);
-    
-    match result.output {
-        Ok(document) => {
-            let pdf_bytes = typst_pdf::pdf(&document, &typst_pdf::PdfOptions::default())
-                .map_err(|e| format!("PDF generation failed: {:?}", e))?;
-            Ok(pdf_bytes)
-        }
-        Err(errors) => {
-            let error_msg = errors
-                .iter()
-                .map(|e| e.message.to_string())
-                .collect::<Vec<_>>()
-                .join("\n");
-            Err(error_msg)
-        }
-    }
-}
-
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tracing_subscriber::fmt()
@@ -511,10 +1310,20 @@ pub fn run() {
     
     let state = Arc::new(AppState {
         world: Mutex::new(None),
+        last_document: Mutex::new(None),
         last_hashes: Mutex::new(Vec::new()),
         last_blocks: Mutex::new(Vec::new()),
         lsp_manager,
         current_file_path: Mutex::new(None),
+        settings: Mutex::new(Settings::default()),
+        semantic_tokens: Mutex::new(SemanticTokenCache::default()),
+        app_handle: std::sync::OnceLock::new(),
+        package_fetcher: std::sync::OnceLock::new(),
+        last_revision: Mutex::new(0),
+        package_index: Mutex::new(None),
+        inputs: Mutex::new(HashMap::new()),
+        preview_server: Mutex::new(None),
+        remote_server: Mutex::new(None),
     });
 
     tauri::async_runtime::spawn(async move {
@@ -524,20 +1333,105 @@ pub fn run() {
         }
     });
 
+    let setup_state = Arc::clone(&state);
+    let exit_manager = Arc::clone(&state.lsp_manager);
+    let exit_state = Arc::clone(&state);
+
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
+        .setup(move |app| {
+            let settings = Settings::load(&Settings::path(app.handle())?);
+            tauri::async_runtime::block_on(
+                setup_state.lsp_manager.set_settings(settings.lsp.clone()),
+            );
+            let remote = settings.remote.clone();
+            *setup_state.settings.lock().map_err(|e| e.to_string())? = settings;
+
+            setup_state.lsp_manager.set_app_handle(app.handle().clone());
+            let _ = setup_state.app_handle.set(app.handle().clone());
+
+            let fetch_app = app.handle().clone();
+            let fetch_state = Arc::downgrade(&setup_state);
+            let fetcher = PackageFetcher::new(
+                Some(download_listener(app.handle().clone())),
+                move |spec| {
+                    tracing::info!("Package {} ready, recompiling", spec);
+                    if let Some(state) = fetch_state.upgrade() {
+                        if let Err(e) = recompile_current(&fetch_app, &state) {
+                            tracing::error!("Recompile after package fetch failed: {}", e);
+                        }
+                    }
+                },
+            );
+            let _ = setup_state.package_fetcher.set(Arc::new(fetcher));
+
+            if let Err(e) = tauri::async_runtime::block_on(restart_remote_server(
+                app.handle().clone(),
+                &setup_state,
+                &remote,
+            )) {
+                tracing::error!("Failed to start remote control: {}", e);
+            }
+            Ok(())
+        })
         .manage(state)
         .invoke_handler(tauri::generate_handler![
             compile_typst,
             compile_and_get,
             lsp_completion,
+            lsp_resolve_completion,
             lsp_hover,
             lsp_goto_definition,
+            lsp_goto_declaration,
+            lsp_goto_type_definition,
+            jump_from_click,
+            lsp_signature_help,
+            lsp_inlay_hints,
+            lsp_references,
+            lsp_prepare_rename,
+            lsp_rename,
+            lsp_code_actions,
+            lsp_execute_command,
+            lsp_format,
+            save_document,
+            document_outline,
+            semantic_tokens,
             lsp_update_document,
+            lsp_status,
+            lsp_retry,
+            lsp_validate_server,
+            get_settings,
+            get_inputs,
+            set_inputs,
+            set_lsp_settings,
+            set_format_settings,
+            set_package_settings,
+            set_compile_settings,
+            list_cached_packages,
+            delete_cached_package,
+            refresh_package_index,
+            search_packages,
+            get_package_info,
+            retry_package_downloads,
+            create_project_from_template,
+            start_preview_server,
+            stop_preview_server,
+            preview_server_url,
+            set_remote_settings,
+            remote_control_info,
             export_pdf
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(move |_app, event| {
+            if let RunEvent::Exit = event {
+                // Remove the remote control connection file
+                if let Some(server) = exit_state.remote_server.lock().ok().and_then(|mut server| server.take()) {
+                    server.stop();
+                }
+                tauri::async_runtime::block_on(exit_manager.shutdown());
+            }
+        });
 }
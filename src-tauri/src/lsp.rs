@@ -1,14 +1,29 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::process::Stdio;
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use tauri::{AppHandle, Emitter};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Child;
 use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
 use tracing::{info, warn};
 
-use std::sync::Arc;
+use crate::settings::LspSettings;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Delay before the first restart attempt after a crash; doubled on each failure.
+const RESTART_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const RESTART_MAX_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_RESTART_ATTEMPTS: u32 = 5;
+/// A server that stays up this long resets the restart budget.
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
+/// How long each shutdown step may take before the process is killed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct JsonRpcRequest {
@@ -50,15 +65,142 @@ pub struct Range {
     pub end: Position,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextDocumentContentChange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextDocumentSyncKind {
+    None,
+    Full,
+    Incremental,
+}
+
+impl TextDocumentSyncKind {
+    fn from_capabilities(capabilities: &Value) -> Self {
+        let sync = &capabilities["textDocumentSync"];
+        let kind = sync
+            .as_u64()
+            .or_else(|| sync.get("change").and_then(|v| v.as_u64()));
+
+        match kind {
+            Some(0) => TextDocumentSyncKind::None,
+            Some(2) => TextDocumentSyncKind::Incremental,
+            _ => TextDocumentSyncKind::Full,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LspStatus {
+    Starting,
+    Running,
+    Crashed,
+    Restarting,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct LspStatusEvent {
+    status: LspStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct OpenDocument {
+    text: String,
+    version: i64,
+    /// The newest version the running server has been sent, 0 for none.
+    sent_version: i64,
+}
+
+impl OpenDocument {
+    fn apply_change(&mut self, change: &TextDocumentContentChange) {
+        match &change.range {
+            Some(range) => {
+                let start = position_to_offset(&self.text, &range.start);
+                let end = position_to_offset(&self.text, &range.end).max(start);
+                self.text.replace_range(start..end, &change.text);
+            }
+            None => self.text = change.text.clone(),
+        }
+    }
+}
+
+/// Converts an LSP position (UTF-16 code units) into a byte offset in `text`.
+pub fn position_to_offset(text: &str, position: &Position) -> usize {
+    let mut offset = 0;
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        if index as u64 == position.line {
+            let mut character = 0;
+            for (byte, ch) in line.char_indices() {
+                if character >= position.character || ch == '\n' || ch == '\r' {
+                    return offset + byte;
+                }
+                character += ch.len_utf16() as u64;
+            }
+            return offset + line.len();
+        }
+        offset += line.len();
+    }
+    text.len()
+}
+
+/// Result of probing a language server binary with `--version`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerInfo {
+    pub path: String,
+    pub version: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompletionItem {
     pub label: String,
     #[serde(rename = "insertText", skip_serializing_if = "Option::is_none")]
     pub insert_text: Option<String>,
+    /// `1` for plain text, `2` when `insertText`/`textEdit` is a snippet.
+    #[serde(rename = "insertTextFormat", skip_serializing_if = "Option::is_none")]
+    pub insert_text_format: Option<u64>,
+    #[serde(rename = "textEdit", skip_serializing_if = "Option::is_none")]
+    pub text_edit: Option<CompletionTextEdit>,
+    #[serde(rename = "additionalTextEdits", default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_text_edits: Vec<TextEdit>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub kind: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub detail: Option<String>,
+    /// A string or `MarkupContent`, as sent by the server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<Value>,
+    #[serde(rename = "sortText", skip_serializing_if = "Option::is_none")]
+    pub sort_text: Option<String>,
+    #[serde(rename = "filterText", skip_serializing_if = "Option::is_none")]
+    pub filter_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<Command>,
+    /// Opaque server data that must be sent back for `completionItem/resolve`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InsertReplaceEdit {
+    #[serde(rename = "newText")]
+    pub new_text: String,
+    pub insert: Range,
+    pub replace: Range,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CompletionTextEdit {
+    Edit(TextEdit),
+    InsertReplace(InsertReplaceEdit),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -68,12 +210,358 @@ pub struct Hover {
     pub range: Option<Range>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParameterLabel {
+    Simple(String),
+    /// Start and end offsets (UTF-16) into the signature label.
+    Offsets([u32; 2]),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParameterInformation {
+    pub label: ParameterLabel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignatureInformation {
+    pub label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<Value>,
+    #[serde(default)]
+    pub parameters: Vec<ParameterInformation>,
+    #[serde(rename = "activeParameter", skip_serializing_if = "Option::is_none")]
+    pub active_parameter: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignatureHelp {
+    pub signatures: Vec<SignatureInformation>,
+    #[serde(rename = "activeSignature", skip_serializing_if = "Option::is_none")]
+    pub active_signature: Option<u32>,
+    #[serde(rename = "activeParameter", skip_serializing_if = "Option::is_none")]
+    pub active_parameter: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InlayHint {
+    pub position: Position,
+    /// The hint text; label parts are joined into a single string.
+    pub label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<u64>,
+    #[serde(rename = "paddingLeft")]
+    pub padding_left: bool,
+    #[serde(rename = "paddingRight")]
+    pub padding_right: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tooltip: Option<Value>,
+}
+
+impl InlayHint {
+    fn from_value(value: &Value) -> Option<Self> {
+        let position = serde_json::from_value::<Position>(value["position"].clone()).ok()?;
+        let label = match &value["label"] {
+            Value::String(label) => label.clone(),
+            Value::Array(parts) => parts
+                .iter()
+                .filter_map(|part| part["value"].as_str())
+                .collect(),
+            _ => return None,
+        };
+
+        Some(InlayHint {
+            position,
+            label,
+            kind: value["kind"].as_u64(),
+            padding_left: value["paddingLeft"].as_bool().unwrap_or(false),
+            padding_right: value["paddingRight"].as_bool().unwrap_or(false),
+            tooltip: value.get("tooltip").cloned(),
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Location {
     pub uri: String,
     pub range: Range,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextEdit {
+    pub range: Range,
+    #[serde(rename = "newText")]
+    pub new_text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentSymbol {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub kind: u64,
+    pub range: Range,
+    #[serde(rename = "selectionRange")]
+    pub selection_range: Range,
+    #[serde(default)]
+    pub children: Vec<DocumentSymbol>,
+}
+
+impl DocumentSymbol {
+    /// Accepts both `DocumentSymbol` and the flat `SymbolInformation` form.
+    fn from_value(value: &Value) -> Option<Self> {
+        if let Ok(symbol) = serde_json::from_value::<DocumentSymbol>(value.clone()) {
+            return Some(symbol);
+        }
+
+        let location = serde_json::from_value::<Location>(value["location"].clone()).ok()?;
+        Some(DocumentSymbol {
+            name: value["name"].as_str()?.to_string(),
+            detail: value["containerName"].as_str().map(str::to_string),
+            kind: value["kind"].as_u64()?,
+            range: location.range.clone(),
+            selection_range: location.range,
+            children: vec![],
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SemanticTokensEdit {
+    pub start: u32,
+    #[serde(rename = "deleteCount")]
+    pub delete_count: u32,
+    #[serde(default)]
+    pub data: Vec<u32>,
+}
+
+/// Names for the token type indices and modifier bits in [`SemanticTokens`] data.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SemanticTokensLegend {
+    #[serde(rename = "tokenTypes", default)]
+    pub token_types: Vec<String>,
+    #[serde(rename = "tokenModifiers", default)]
+    pub token_modifiers: Vec<String>,
+}
+
+/// Semantic tokens in the LSP relative encoding, either the full set or the
+/// edits against the result identified by the request's previous result id.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SemanticTokens {
+    Full {
+        #[serde(rename = "resultId")]
+        result_id: Option<String>,
+        legend: SemanticTokensLegend,
+        data: Vec<u32>,
+    },
+    Delta {
+        #[serde(rename = "resultId")]
+        result_id: Option<String>,
+        legend: SemanticTokensLegend,
+        edits: Vec<SemanticTokensEdit>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub range: Range,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Command {
+    pub title: String,
+    pub command: String,
+    #[serde(default)]
+    pub arguments: Vec<Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CodeAction {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(rename = "isPreferred")]
+    pub is_preferred: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edit: Option<WorkspaceEdit>,
+    /// Run through `workspace/executeCommand` after applying `edit`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<Command>,
+}
+
+impl CodeAction {
+    /// Accepts both `CodeAction` literals and bare `Command`s.
+    fn from_value(value: &Value) -> Option<Self> {
+        let title = value["title"].as_str()?.to_string();
+
+        if value["command"].is_string() {
+            let command = serde_json::from_value::<Command>(value.clone()).ok()?;
+            return Some(CodeAction {
+                title,
+                kind: None,
+                is_preferred: false,
+                diagnostics: vec![],
+                edit: None,
+                command: Some(command),
+            });
+        }
+
+        Some(CodeAction {
+            title,
+            kind: value["kind"].as_str().map(str::to_string),
+            is_preferred: value["isPreferred"].as_bool().unwrap_or(false),
+            diagnostics: serde_json::from_value(value["diagnostics"].clone()).unwrap_or_default(),
+            edit: value.get("edit").map(WorkspaceEdit::from_value),
+            command: serde_json::from_value(value["command"].clone()).ok(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormattingOptions {
+    #[serde(rename = "tabSize")]
+    pub tab_size: u32,
+    #[serde(rename = "insertSpaces")]
+    pub insert_spaces: bool,
+}
+
+/// Applies `edits` (all relative to the original `text`) and returns the new text.
+pub fn apply_text_edits(text: &str, edits: &[TextEdit]) -> String {
+    let mut spans: Vec<(usize, usize, &str)> = edits
+        .iter()
+        .map(|edit| {
+            let start = position_to_offset(text, &edit.range.start);
+            let end = position_to_offset(text, &edit.range.end).max(start);
+            (start, end, edit.new_text.as_str())
+        })
+        .collect();
+    // Stable, so edits on the same range keep their array order; applying back
+    // to front then leaves equal-position inserts in that order too
+    spans.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+
+    let mut result = text.to_string();
+    for (start, end, new_text) in spans.into_iter().rev() {
+        result.replace_range(start..end, new_text);
+    }
+    result
+}
+
+fn ranges_overlap(a: &Range, b: &Range) -> bool {
+    let key = |p: &Position| (p.line, p.character);
+    key(&a.start) <= key(&b.end) && key(&b.start) <= key(&a.end)
+}
+
+/// Edits grouped by document URI; `documentChanges` responses are flattened into this form.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WorkspaceEdit {
+    pub changes: HashMap<String, Vec<TextEdit>>,
+}
+
+impl WorkspaceEdit {
+    fn from_value(value: &Value) -> Self {
+        let mut edit = WorkspaceEdit::default();
+
+        if let Some(changes) = value.get("changes").and_then(|v| v.as_object()) {
+            for (uri, edits) in changes {
+                if let Ok(edits) = serde_json::from_value::<Vec<TextEdit>>(edits.clone()) {
+                    edit.changes.entry(uri.clone()).or_default().extend(edits);
+                }
+            }
+        }
+
+        if let Some(document_changes) = value.get("documentChanges").and_then(|v| v.as_array()) {
+            for change in document_changes {
+                let uri = change["textDocument"]["uri"].as_str();
+                let edits = serde_json::from_value::<Vec<TextEdit>>(change["edits"].clone());
+                match (uri, edits) {
+                    (Some(uri), Ok(edits)) => {
+                        edit.changes.entry(uri.to_string()).or_default().extend(edits);
+                    }
+                    _ => warn!("Skipping unsupported workspace edit operation: {}", change),
+                }
+            }
+        }
+
+        edit
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrepareRename {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub placeholder: Option<String>,
+}
+
+impl PrepareRename {
+    /// Accepts the three response shapes: `Range`, `{ range, placeholder }` and `{ defaultBehavior }`.
+    fn from_value(value: &Value) -> Option<Self> {
+        if let Ok(range) = serde_json::from_value::<Range>(value.clone()) {
+            return Some(PrepareRename { range: Some(range), placeholder: None });
+        }
+
+        let range = serde_json::from_value::<Range>(value["range"].clone()).ok();
+        let placeholder = value["placeholder"].as_str().map(str::to_string);
+        if range.is_some() || placeholder.is_some() {
+            return Some(PrepareRename { range, placeholder });
+        }
+
+        if value["defaultBehavior"].as_bool() == Some(true) {
+            return Some(PrepareRename { range: None, placeholder: None });
+        }
+
+        None
+    }
+}
+
+/// A goto target; plain `Location` results use their range for both ranges.
+#[derive(Debug, Clone, Serialize)]
+pub struct DefinitionLink {
+    pub uri: String,
+    /// The full range of the target, e.g. a whole `#let` binding.
+    pub range: Range,
+    /// The part to select when jumping, e.g. the bound name.
+    #[serde(rename = "selectionRange")]
+    pub selection_range: Range,
+    #[serde(rename = "originSelectionRange", skip_serializing_if = "Option::is_none")]
+    pub origin_selection_range: Option<Range>,
+}
+
+impl DefinitionLink {
+    fn from_value(value: &Value) -> Option<Self> {
+        if let Ok(location) = serde_json::from_value::<Location>(value.clone()) {
+            return Some(DefinitionLink {
+                uri: location.uri,
+                range: location.range.clone(),
+                selection_range: location.range,
+                origin_selection_range: None,
+            });
+        }
+
+        let range = serde_json::from_value::<Range>(value["targetRange"].clone()).ok()?;
+        Some(DefinitionLink {
+            uri: value["targetUri"].as_str()?.to_string(),
+            selection_range: serde_json::from_value(value["targetSelectionRange"].clone())
+                .unwrap_or_else(|_| range.clone()),
+            range,
+            origin_selection_range: serde_json::from_value(value["originSelectionRange"].clone()).ok(),
+        })
+    }
+}
+
 struct LspChannel {
     sender: mpsc::Sender<(u64, Value)>,
     receiver: Arc<Mutex<mpsc::Receiver<(u64, Value)>>>,
@@ -116,13 +604,30 @@ impl LspChannel {
     }
 }
 
+#[derive(Clone)]
 pub struct LspManager {
     process: Arc<Mutex<Option<Child>>>,
     stdin: Arc<Mutex<Option<tokio::process::ChildStdin>>>,
     request_id: Arc<Mutex<u64>>,
-    document_version: Arc<Mutex<HashMap<String, i64>>>,
+    documents: Arc<Mutex<HashMap<String, OpenDocument>>>,
     initialized: Arc<Mutex<bool>>,
+    /// Held while a server is being spawned so only one process runs at a time.
+    start_lock: Arc<Mutex<()>>,
     response_tx: Arc<Mutex<Option<LspChannel>>>,
+    sync_kind: Arc<Mutex<TextDocumentSyncKind>>,
+    capabilities: Arc<Mutex<Value>>,
+    /// Last `textDocument/publishDiagnostics` per document URI.
+    diagnostics: Arc<Mutex<HashMap<String, Vec<Diagnostic>>>>,
+    status: Arc<Mutex<LspStatus>>,
+    restart_attempts: Arc<Mutex<u32>>,
+    shutting_down: Arc<AtomicBool>,
+    /// Bumped by [`LspManager::stop`] so the stopped process's supervisor does not restart it.
+    generation: Arc<AtomicU64>,
+    /// Why the server last failed to start; requests fail fast with it until
+    /// the settings change or [`LspManager::retry`] is called.
+    start_failure: Arc<Mutex<Option<String>>>,
+    settings: Arc<Mutex<LspSettings>>,
+    app_handle: Arc<OnceLock<AppHandle>>,
 }
 
 impl LspManager {
@@ -131,10 +636,89 @@ impl LspManager {
             process: Arc::new(Mutex::new(None)),
             stdin: Arc::new(Mutex::new(None)),
             request_id: Arc::new(Mutex::new(1)),
-            document_version: Arc::new(Mutex::new(HashMap::new())),
+            documents: Arc::new(Mutex::new(HashMap::new())),
             initialized: Arc::new(Mutex::new(false)),
+            start_lock: Arc::new(Mutex::new(())),
             response_tx: Arc::new(Mutex::new(None)),
+            sync_kind: Arc::new(Mutex::new(TextDocumentSyncKind::Full)),
+            capabilities: Arc::new(Mutex::new(Value::Null)),
+            diagnostics: Arc::new(Mutex::new(HashMap::new())),
+            status: Arc::new(Mutex::new(LspStatus::Starting)),
+            restart_attempts: Arc::new(Mutex::new(0)),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            generation: Arc::new(AtomicU64::new(0)),
+            start_failure: Arc::new(Mutex::new(None)),
+            settings: Arc::new(Mutex::new(LspSettings::default())),
+            app_handle: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Sets the handle used to emit `lsp-status` events to the frontend.
+    pub fn set_app_handle(&self, app: AppHandle) {
+        let _ = self.app_handle.set(app);
+    }
+
+    /// Replaces the launch settings; they take effect the next time the server starts.
+    pub async fn set_settings(&self, settings: LspSettings) {
+        *self.settings.lock().await = settings;
+        *self.start_failure.lock().await = None;
+    }
+
+    /// Forgets the last start failure and tries to start the server again.
+    pub async fn retry(&self) -> Result<(), String> {
+        *self.start_failure.lock().await = None;
+        *self.restart_attempts.lock().await = 0;
+        self.initialize().await
+    }
+
+    pub async fn status(&self) -> LspStatus {
+        *self.status.lock().await
+    }
+
+    async fn set_status(&self, status: LspStatus, message: Option<String>) {
+        *self.status.lock().await = status;
+
+        if let Some(app) = self.app_handle.get() {
+            let payload = LspStatusEvent { status, message };
+            if let Err(e) = app.emit("lsp-status", payload) {
+                warn!("Failed to emit lsp-status event: {}", e);
+            }
+        }
+    }
+
+    async fn resolve_server_path(settings: &LspSettings) -> Result<String, String> {
+        match settings.server_path.as_deref().map(str::trim) {
+            Some(path) if !path.is_empty() => Ok(path.to_string()),
+            _ => Self::find_tinymist_executable().await,
+        }
+    }
+
+    /// Runs `<server> --version` to check that the configured binary is usable.
+    pub async fn validate_server(settings: &LspSettings) -> Result<ServerInfo, String> {
+        let path = Self::resolve_server_path(settings).await?;
+
+        let output = tokio::time::timeout(
+            Duration::from_secs(5),
+            tokio::process::Command::new(&path)
+                .arg("--version")
+                .envs(&settings.env)
+                .output(),
+        )
+        .await
+        .map_err(|_| format!("Timed out running {} --version", path))?
+        .map_err(|e| format!("Failed to run {}: {}", path, e))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "{} --version exited with {}: {}",
+                path,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
         }
+
+        let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok(ServerInfo { path, version })
     }
 
     async fn find_tinymist_executable() -> Result<String, String> {
@@ -181,26 +765,139 @@ impl LspManager {
         Ok("tinymist".to_string())
     }
 
-    async fn spawn_reader_task(
+    /// Reads the server's messages: responses go to `response_tx`, server
+    /// requests are answered and notifications are handled in place.
+    fn spawn_reader_task(
+        &self,
         stdout: tokio::process::ChildStdout,
         response_tx: LspChannel,
-    ) {
+    ) -> JoinHandle<()> {
+        let manager = self.clone();
         tokio::spawn(async move {
             let reader = BufReader::new(stdout);
             let mut lines = reader.lines();
             
             while let Ok(Some(line)) = lines.next_line().await {
-                tracing::debug!("LSP response: {}", line);
-                
-                if let Ok(response) = serde_json::from_str::<JsonRpcResponse>(&line) {
-                    if let Some(result) = response.result {
-                        let _ = response_tx.send(response.id, result).await;
-                    } else if let Some(error) = response.error {
-                        warn!("LSP error: {} - {}", error.code, error.message);
+                tracing::debug!("LSP message: {}", line);
+
+                let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                    warn!("Ignoring malformed LSP message: {}", line);
+                    continue;
+                };
+
+                match (message.get("id").cloned(), message["method"].as_str()) {
+                    (Some(id), Some(method)) => {
+                        // Answered off the reader so replies never wait on it
+                        let manager = manager.clone();
+                        let method = method.to_string();
+                        let params = message["params"].clone();
+                        tokio::spawn(async move { manager.handle_server_request(id, &method, params).await });
+                    }
+                    (None, Some(method)) => manager.handle_notification(method, &message["params"]).await,
+                    _ => {
+                        if let Ok(response) = serde_json::from_value::<JsonRpcResponse>(message) {
+                            if let Some(result) = response.result {
+                                let _ = response_tx.send(response.id, result).await;
+                            } else if let Some(error) = response.error {
+                                warn!("LSP error: {} - {}", error.code, error.message);
+                            }
+                        }
                     }
                 }
             }
-        });
+        })
+    }
+
+    async fn handle_notification(&self, method: &str, params: &Value) {
+        match method {
+            "textDocument/publishDiagnostics" => {
+                let Some(uri) = params["uri"].as_str() else {
+                    return;
+                };
+                let diagnostics =
+                    serde_json::from_value::<Vec<Diagnostic>>(params["diagnostics"].clone()).unwrap_or_default();
+                self.diagnostics.lock().await.insert(uri.to_string(), diagnostics.clone());
+
+                if let Some(app) = self.app_handle.get() {
+                    let payload = serde_json::json!({ "uri": uri, "diagnostics": diagnostics });
+                    if let Err(e) = app.emit("lsp-diagnostics", payload) {
+                        warn!("Failed to emit lsp-diagnostics event: {}", e);
+                    }
+                }
+            }
+            "window/logMessage" | "window/showMessage" => {
+                info!("LSP server: {}", params["message"].as_str().unwrap_or_default());
+            }
+            _ => tracing::debug!("Unhandled LSP notification: {}", method),
+        }
+    }
+
+    /// Replies to a request the server sent us; unknown methods get `MethodNotFound`.
+    async fn handle_server_request(&self, id: Value, method: &str, params: Value) {
+        let outcome = match method {
+            "workspace/applyEdit" => {
+                let edit = WorkspaceEdit::from_value(&params["edit"]);
+                Ok(match self.apply_workspace_edit(edit).await {
+                    Ok(()) => serde_json::json!({ "applied": true }),
+                    Err(e) => {
+                        warn!("Failed to apply workspace edit: {}", e);
+                        serde_json::json!({ "applied": false, "failureReason": e })
+                    }
+                })
+            }
+            "workspace/configuration" => {
+                let items = params["items"].as_array().map_or(0, Vec::len);
+                Ok(Value::Array(vec![Value::Null; items]))
+            }
+            "window/workDoneProgress/create"
+            | "client/registerCapability"
+            | "client/unregisterCapability"
+            | "window/showMessageRequest" => Ok(Value::Null),
+            _ => Err(serde_json::json!({
+                "code": -32601,
+                "message": format!("Unsupported method: {}", method)
+            })),
+        };
+
+        let reply = match outcome {
+            Ok(result) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+        };
+        if let Err(e) = self.write_message(&reply).await {
+            warn!("Failed to answer LSP request {}: {}", method, e);
+        }
+    }
+
+    /// Applies a server-initiated edit. Documents open in the editor are handed
+    /// to the frontend as an `lsp-apply-edit` event (its buffer is the source of
+    /// truth and syncs back); other files are edited on disk.
+    async fn apply_workspace_edit(&self, edit: WorkspaceEdit) -> Result<(), String> {
+        let open: HashSet<String> = self.documents.lock().await.keys().cloned().collect();
+        let mut forwarded = WorkspaceEdit::default();
+
+        for (uri, edits) in edit.changes {
+            if open.contains(&uri) {
+                forwarded.changes.insert(uri, edits);
+                continue;
+            }
+
+            let path = url::Url::parse(&uri)
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+                .ok_or_else(|| format!("Cannot edit non-file URI {}", uri))?;
+            let text = tokio::fs::read_to_string(&path)
+                .await
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            tokio::fs::write(&path, apply_text_edits(&text, &edits))
+                .await
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        }
+
+        if forwarded.changes.is_empty() {
+            return Ok(());
+        }
+        let app = self.app_handle.get().ok_or("No editor window to apply the edit to")?;
+        app.emit("lsp-apply-edit", forwarded).map_err(|e| e.to_string())
     }
 
     pub async fn initialize(&self) -> Result<(), String> {
@@ -210,12 +907,43 @@ impl LspManager {
         }
         drop(initialized);
 
+        self.set_status(LspStatus::Starting, None).await;
+
+        match self.start_process().await {
+            Ok(()) => {
+                self.set_status(LspStatus::Running, None).await;
+                Ok(())
+            }
+            Err(e) => {
+                *self.start_failure.lock().await = Some(e.clone());
+                self.set_status(LspStatus::Crashed, Some(e.clone())).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Starts the server unless another caller already did while we waited for
+    /// the start lock; tracked documents are re-opened on the new process.
+    async fn start_process(&self) -> Result<(), String> {
+        let _start = self.start_lock.lock().await;
+        if *self.initialized.lock().await {
+            return Ok(());
+        }
+
+        self.spawn_process().await?;
+        self.replay_documents().await;
+        Ok(())
+    }
+
+    async fn spawn_process(&self) -> Result<(), String> {
         info!("Starting Tinymist LSP server...");
 
-        let tinymist_path = Self::find_tinymist_executable().await?;
+        let settings = self.settings.lock().await.clone();
+        let tinymist_path = Self::resolve_server_path(&settings).await?;
 
         let mut child = tokio::process::Command::new(&tinymist_path)
-            .args(["lsp"])
+            .args(&settings.args)
+            .envs(&settings.env)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
@@ -224,35 +952,115 @@ impl LspManager {
 
         let child_stdin = child.stdin.take().ok_or("Failed to take stdin")?;
         let child_stdout = child.stdout.take().ok_or("Failed to take stdout")?;
+        if let Some(stderr) = child.stderr.take() {
+            // Drained so a chatty server never blocks on a full pipe
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    tracing::debug!("tinymist: {}", line);
+                }
+            });
+        }
 
         let (tx, rx) = LspChannel::new(100);
         {
             let mut tx_guard = self.response_tx.lock().await;
-            *tx_guard = Some(rx);
+            *tx_guard = Some(rx.clone());
         }
 
-        Self::spawn_reader_task(child_stdout, tx).await;
+        let reader = self.spawn_reader_task(child_stdout, tx);
 
         {
             let mut stdin_guard = self.stdin.lock().await;
             *stdin_guard = Some(child_stdin);
         }
 
+        let mut init_params = serde_json::json!({
+            "rootUri": "file:///workspace",
+            "workspaceFolders": [],
+            "capabilities": {
+                "textDocument": {
+                    "synchronization": { "dynamicRegistration": false },
+                    "definition": { "linkSupport": true },
+                    "declaration": { "linkSupport": true },
+                    "typeDefinition": { "linkSupport": true },
+                    "completion": {
+                        "completionItem": {
+                            "snippetSupport": true,
+                            "insertReplaceSupport": true,
+                            "documentationFormat": ["markdown", "plaintext"],
+                            "resolveSupport": {
+                                "properties": ["documentation", "detail", "additionalTextEdits"]
+                            }
+                        }
+                    },
+                    "semanticTokens": {
+                        "requests": { "full": { "delta": true } },
+                        "formats": ["relative"]
+                    }
+                }
+            }
+        });
+        if let Some(options) = settings.initialization_options {
+            init_params["initializationOptions"] = options;
+        }
+
+        if let Err(e) = self.handshake(init_params, &rx).await {
+            // Never leave a half-started server running next to the next attempt
+            reader.abort();
+            *self.stdin.lock().await = None;
+            *self.response_tx.lock().await = None;
+            if let Err(kill_error) = child.kill().await {
+                warn!("Failed to kill Tinymist LSP server: {}", kill_error);
+            }
+            return Err(e);
+        }
+
+        {
+            let mut process_guard = self.process.lock().await;
+            *process_guard = Some(child);
+        }
+
+        {
+            let mut init_flag = self.initialized.lock().await;
+            *init_flag = true;
+        }
+
+        self.spawn_supervisor(reader);
+
+        info!("Tinymist LSP server initialized successfully");
+        Ok(())
+    }
+
+    /// Sends `initialize`, records the server capabilities and sends `initialized`.
+    async fn handshake(&self, init_params: Value, rx: &LspChannel) -> Result<(), String> {
         let init_id = 1u64;
         let init_request = JsonRpcRequest {
             jsonrpc: "2.0".to_string(),
             id: init_id,
             method: "initialize".to_string(),
-            params: Some(serde_json::json!({
-                "rootUri": "file:///workspace",
-                "workspaceFolders": [],
-                "capabilities": {}
-            })),
+            params: Some(init_params),
         };
 
         self.send_json_request(init_id, &init_request).await?;
 
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        // Wait for the initialize result so we know which sync mode the server accepts
+        let init_result = tokio::time::timeout(
+            tokio::time::Duration::from_secs(5),
+            rx.recv(),
+        )
+        .await;
+
+        match init_result {
+            Ok(Some((resp_id, result))) if resp_id == init_id => {
+                let kind = TextDocumentSyncKind::from_capabilities(&result["capabilities"]);
+                info!("LSP server text document sync: {:?}", kind);
+                *self.sync_kind.lock().await = kind;
+                *self.capabilities.lock().await = result["capabilities"].clone();
+            }
+            Ok(_) => return Err("LSP server closed before answering initialize".to_string()),
+            Err(_) => return Err("Timed out waiting for the LSP initialize result".to_string()),
+        }
 
         let initialized_request = JsonRpcRequest {
             jsonrpc: "2.0".to_string(),
@@ -260,27 +1068,147 @@ impl LspManager {
             method: "initialized".to_string(),
             params: None,
         };
-        self.send_json_request(0, &initialized_request).await?;
+        self.send_json_request(0, &initialized_request).await
+    }
 
-        {
-            let mut process_guard = self.process.lock().await;
-            *process_guard = Some(child);
+    /// Watches the server's stdout reader; when it ends the process is gone and
+    /// a restart with backoff is scheduled.
+    fn spawn_supervisor(&self, reader: JoinHandle<()>) {
+        let manager = self.clone();
+        let started_at = Instant::now();
+        let generation = self.generation.load(Ordering::SeqCst);
+
+        tokio::spawn(async move {
+            let _ = reader.await;
+
+            // An orderly shutdown or stop cleans up the process itself
+            if manager.shutting_down.load(Ordering::SeqCst)
+                || manager.generation.load(Ordering::SeqCst) != generation
+            {
+                return;
+            }
+
+            // Mark the restart before clearing `initialized` so requests in
+            // between do not start a second server via `ensure_started`
+            manager
+                .set_status(LspStatus::Restarting, Some("Server exited unexpectedly".to_string()))
+                .await;
+            let exit = manager.reset_process().await;
+            warn!("Tinymist LSP server exited unexpectedly: {}", exit);
+
+            if started_at.elapsed() >= STABLE_UPTIME {
+                *manager.restart_attempts.lock().await = 0;
+            }
+            manager.restart_with_backoff().await;
+        });
+    }
+
+    /// Drops the dead process and its pipes and describes how it exited.
+    async fn reset_process(&self) -> String {
+        let _start = self.start_lock.lock().await;
+        *self.initialized.lock().await = false;
+        *self.stdin.lock().await = None;
+        *self.response_tx.lock().await = None;
+        self.diagnostics.lock().await.clear();
+
+        let child = self.process.lock().await.take();
+        match child {
+            Some(mut child) => match child.try_wait() {
+                Ok(Some(status)) => status.to_string(),
+                Ok(None) => {
+                    let _ = child.kill().await;
+                    "stdout closed".to_string()
+                }
+                Err(e) => e.to_string(),
+            },
+            None => "process not running".to_string(),
         }
+    }
 
-        {
-            let mut init_flag = self.initialized.lock().await;
-            *init_flag = true;
+    async fn restart_with_backoff(&self) {
+        loop {
+            let attempt = {
+                let mut attempts = self.restart_attempts.lock().await;
+                *attempts += 1;
+                *attempts
+            };
+
+            if attempt > MAX_RESTART_ATTEMPTS {
+                warn!("Giving up on restarting Tinymist after {} attempts", MAX_RESTART_ATTEMPTS);
+                self.set_status(
+                    LspStatus::Crashed,
+                    Some(format!("Restart failed after {} attempts", MAX_RESTART_ATTEMPTS)),
+                )
+                .await;
+                *self.restart_attempts.lock().await = 0;
+                *self.start_failure.lock().await =
+                    Some(format!("Restart failed after {} attempts", MAX_RESTART_ATTEMPTS));
+                return;
+            }
+
+            let delay = RESTART_INITIAL_BACKOFF
+                .saturating_mul(1 << (attempt - 1))
+                .min(RESTART_MAX_BACKOFF);
+            self.set_status(
+                LspStatus::Restarting,
+                Some(format!("Attempt {} of {} in {}s", attempt, MAX_RESTART_ATTEMPTS, delay.as_secs())),
+            )
+            .await;
+            tokio::time::sleep(delay).await;
+
+            match self.start_process().await {
+                Ok(()) => {
+                    self.set_status(LspStatus::Running, None).await;
+                    return;
+                }
+                Err(e) => warn!("Restart attempt {} failed: {}", attempt, e),
+            }
         }
+    }
 
-        info!("Tinymist LSP server initialized successfully");
-        Ok(())
+    /// Re-opens every tracked document on a freshly started server.
+    async fn replay_documents(&self) {
+        let documents: Vec<(String, OpenDocument)> = {
+            let mut documents = self.documents.lock().await;
+            documents
+                .iter_mut()
+                .map(|(uri, doc)| {
+                    doc.sent_version = doc.version;
+                    (uri.clone(), doc.clone())
+                })
+                .collect()
+        };
+
+        info!("Replaying {} open documents to LSP server", documents.len());
+        for (uri, doc) in documents {
+            if let Err(e) = self.send_did_open(&uri, &doc).await {
+                warn!("Failed to reopen {}: {}", uri, e);
+            }
+        }
+    }
+
+    async fn send_did_open(&self, uri: &str, doc: &OpenDocument) -> Result<(), String> {
+        let params = serde_json::json!({
+            "textDocument": {
+                "uri": uri,
+                "languageId": "typst",
+                "version": doc.version,
+                "text": doc.text
+            }
+        });
+
+        self.send_notification("textDocument/didOpen", Some(params)).await
     }
 
     async fn send_json_request(&self, _id: u64, request: &JsonRpcRequest) -> Result<(), String> {
+        self.write_message(request).await
+    }
+
+    async fn write_message<T: Serialize>(&self, message: &T) -> Result<(), String> {
         let mut stdin_guard = self.stdin.lock().await;
         let stdin = stdin_guard.as_mut().ok_or("LSP not initialized")?;
 
-        let request_json = serde_json::to_string(request)
+        let request_json = serde_json::to_string(message)
             .map_err(|e| format!("Failed to serialize: {}", e))?;
         
         stdin.write_all(request_json.as_bytes()).await
@@ -299,10 +1227,81 @@ impl LspManager {
             return Ok(());
         }
         drop(initialized);
-        
+
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Err("LSP server is shutting down".to_string());
+        }
+
+        if self.status().await == LspStatus::Restarting {
+            return Err("LSP server is restarting".to_string());
+        }
+
+        if let Some(failure) = self.start_failure.lock().await.clone() {
+            return Err(failure);
+        }
+
         self.initialize().await
     }
 
+    /// Sends `shutdown` and `exit` to the server and waits for it to terminate,
+    /// killing the process if any step exceeds the timeout.
+    pub async fn shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        self.stop().await;
+    }
+
+    /// Stops the running server, if any, with the same handshake as
+    /// [`LspManager::shutdown`]; the next request starts a new one, picking up
+    /// changed settings. Open documents are kept and replayed then.
+    pub async fn stop(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        if *self.initialized.lock().await {
+            info!("Shutting down Tinymist LSP server...");
+
+            let exit_notification = JsonRpcRequest {
+                jsonrpc: "2.0".to_string(),
+                id: 0,
+                method: "exit".to_string(),
+                params: None,
+            };
+
+            let graceful = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+                self.send_request("shutdown", None).await?;
+                self.send_json_request(0, &exit_notification).await
+            })
+            .await;
+
+            match graceful {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("LSP shutdown handshake failed: {}", e),
+                Err(_) => warn!("LSP shutdown handshake timed out"),
+            }
+        }
+
+        let _start = self.start_lock.lock().await;
+        *self.initialized.lock().await = false;
+        *self.stdin.lock().await = None;
+        *self.response_tx.lock().await = None;
+        self.diagnostics.lock().await.clear();
+
+        let child = self.process.lock().await.take();
+        if let Some(mut child) = child {
+            match tokio::time::timeout(SHUTDOWN_TIMEOUT, child.wait()).await {
+                Ok(Ok(status)) => info!("Tinymist LSP server exited: {}", status),
+                Ok(Err(e)) => warn!("Failed to wait for Tinymist LSP server: {}", e),
+                Err(_) => {
+                    warn!("Tinymist LSP server did not exit in time, killing it");
+                    if let Err(e) = child.kill().await {
+                        warn!("Failed to kill Tinymist LSP server: {}", e);
+                    }
+                }
+            }
+        }
+
+        self.set_status(LspStatus::Stopped, None).await;
+    }
+
     pub async fn send_request(&self, method: &str, params: Option<Value>) -> Result<Value, String> {
         self.ensure_started().await?;
 
@@ -369,28 +1368,105 @@ impl LspManager {
         Ok(())
     }
 
-    pub async fn update_document(&self, uri: String, content: String, version: i64) -> Result<(), String> {
-        self.ensure_started().await?;
+    pub async fn sync_kind(&self) -> TextDocumentSyncKind {
+        *self.sync_kind.lock().await
+    }
 
+    /// Sends `textDocument/didChange`, forwarding `changes` as range edits when
+    /// the server accepts incremental sync and falling back to the full text otherwise.
+    pub async fn update_document(
+        &self,
+        uri: String,
+        content: Option<String>,
+        version: i64,
+        changes: Option<Vec<TextDocumentContentChange>>,
+    ) -> Result<(), String> {
+        let changes = changes.filter(|changes| !changes.is_empty());
+
+        // Recorded before the server is started so a start or restart replays
+        // the edit; the first update for a document opens it
         {
-            let mut versions = self.document_version.lock().await;
-            versions.insert(uri.clone(), version);
+            let mut documents = self.documents.lock().await;
+            match documents.get_mut(&uri) {
+                Some(doc) => {
+                    match (&changes, content) {
+                        (Some(changes), _) => {
+                            for change in changes {
+                                doc.apply_change(change);
+                            }
+                        }
+                        (None, Some(content)) => doc.text = content,
+                        (None, None) => return Err("No content or changes given".to_string()),
+                    }
+                    doc.version = version;
+                }
+                None => {
+                    let content = content.ok_or_else(|| {
+                        format!("Document {} is not open; the full content is required", uri)
+                    })?;
+                    documents.insert(uri.clone(), OpenDocument { text: content, version, sent_version: 0 });
+                }
+            }
         }
 
+        self.ensure_started().await?;
+
+        let (doc, opened) = {
+            let mut documents = self.documents.lock().await;
+            let Some(doc) = documents.get_mut(&uri) else {
+                return Ok(());
+            };
+            // A replay while starting already sent this version
+            if doc.sent_version >= version {
+                return Ok(());
+            }
+            let opened = doc.sent_version == 0;
+            doc.sent_version = version;
+            (doc.clone(), opened)
+        };
+
+        if opened {
+            return self.send_did_open(&uri, &doc).await;
+        }
+
+        let content_changes = match (self.sync_kind().await, changes) {
+            (TextDocumentSyncKind::None, _) => return Ok(()),
+            (TextDocumentSyncKind::Incremental, Some(changes)) => changes,
+            _ => vec![TextDocumentContentChange { range: None, text: doc.text }],
+        };
+
         let params = serde_json::json!({
             "textDocument": {
                 "uri": uri,
                 "version": version
             },
-            "contentChanges": [
-                { "text": content }
-            ]
+            "contentChanges": content_changes
         });
 
         self.send_notification("textDocument/didChange", Some(params)).await?;
         Ok(())
     }
 
+    /// Sends `content` as the new text of `uri` under the version after the
+    /// tracked one, for text replaced outside the editor. Returns that version.
+    pub async fn replace_text(&self, uri: String, content: String) -> Result<i64, String> {
+        let version = self.documents.lock().await.get(&uri).map_or(1, |doc| doc.version + 1);
+        self.update_document(uri, Some(content), version, None).await?;
+        Ok(version)
+    }
+
+    /// Makes sure the server holds exactly `content` for `uri`, sending it as
+    /// `version` when the tracked copy differs. The version comes from the
+    /// editor, which numbers all of its updates.
+    pub async fn sync_text(&self, uri: String, content: String, version: i64) -> Result<(), String> {
+        if let Some(doc) = self.documents.lock().await.get(&uri) {
+            if doc.text == content {
+                return Ok(());
+            }
+        }
+        self.update_document(uri, Some(content), version, None).await
+    }
+
     pub async fn get_completion(&self, uri: String, line: u64, character: u64, version: i64) -> Result<Vec<CompletionItem>, String> {
         self.ensure_started().await?;
 
@@ -429,6 +1505,22 @@ impl LspManager {
         }
     }
 
+    /// Fills in lazily computed fields (documentation, edits) of a completion item.
+    pub async fn resolve_completion(&self, item: CompletionItem) -> Result<CompletionItem, String> {
+        self.ensure_started().await?;
+
+        let params = serde_json::to_value(&item)
+            .map_err(|e| format!("Failed to serialize: {}", e))?;
+
+        match self.send_request("completionItem/resolve", Some(params)).await {
+            Ok(result) => Ok(serde_json::from_value::<CompletionItem>(result).unwrap_or(item)),
+            Err(e) => {
+                warn!("Completion resolve request failed: {}", e);
+                Ok(item)
+            }
+        }
+    }
+
     pub async fn get_hover(&self, uri: String, line: u64, character: u64, version: i64) -> Result<Option<Hover>, String> {
         self.ensure_started().await?;
 
@@ -454,7 +1546,21 @@ impl LspManager {
         }
     }
 
-    pub async fn goto_definition(&self, uri: String, line: u64, character: u64, version: i64) -> Result<Option<Location>, String> {
+    pub async fn goto_definition(&self, uri: String, line: u64, character: u64, version: i64) -> Result<Vec<DefinitionLink>, String> {
+        self.goto("textDocument/definition", uri, line, character, version).await
+    }
+
+    pub async fn goto_declaration(&self, uri: String, line: u64, character: u64, version: i64) -> Result<Vec<DefinitionLink>, String> {
+        self.goto("textDocument/declaration", uri, line, character, version).await
+    }
+
+    pub async fn goto_type_definition(&self, uri: String, line: u64, character: u64, version: i64) -> Result<Vec<DefinitionLink>, String> {
+        self.goto("textDocument/typeDefinition", uri, line, character, version).await
+    }
+
+    /// Shared implementation of the goto requests, which all answer with
+    /// `Location | Location[] | LocationLink[] | null`.
+    async fn goto(&self, method: &str, uri: String, line: u64, character: u64, version: i64) -> Result<Vec<DefinitionLink>, String> {
         self.ensure_started().await?;
 
         let params = serde_json::json!({
@@ -462,28 +1568,308 @@ impl LspManager {
             "position": { "line": line, "character": character }
         });
 
-        match self.send_request("textDocument/definition", Some(params)).await {
+        match self.send_request(method, Some(params)).await {
+            Ok(result) => {
+                if result.is_null() {
+                    return Ok(vec![]);
+                }
+                if let Some(link) = DefinitionLink::from_value(&result) {
+                    return Ok(vec![link]);
+                }
+                Ok(result
+                    .as_array()
+                    .map(|targets| targets.iter().filter_map(DefinitionLink::from_value).collect())
+                    .unwrap_or_default())
+            }
+            Err(e) => {
+                warn!("{} request failed: {}", method, e);
+                Ok(vec![])
+            }
+        }
+    }
+
+    pub async fn find_references(
+        &self,
+        uri: String,
+        line: u64,
+        character: u64,
+        version: i64,
+        include_declaration: bool,
+    ) -> Result<Vec<Location>, String> {
+        self.ensure_started().await?;
+
+        let params = serde_json::json!({
+            "textDocument": { "uri": uri, "version": version },
+            "position": { "line": line, "character": character },
+            "context": { "includeDeclaration": include_declaration }
+        });
+
+        match self.send_request("textDocument/references", Some(params)).await {
+            Ok(result) => Ok(serde_json::from_value::<Vec<Location>>(result).unwrap_or_default()),
+            Err(e) => {
+                warn!("References request failed: {}", e);
+                Ok(vec![])
+            }
+        }
+    }
+
+    pub async fn prepare_rename(&self, uri: String, line: u64, character: u64, version: i64) -> Result<Option<PrepareRename>, String> {
+        self.ensure_started().await?;
+
+        let params = serde_json::json!({
+            "textDocument": { "uri": uri, "version": version },
+            "position": { "line": line, "character": character }
+        });
+
+        match self.send_request("textDocument/prepareRename", Some(params)).await {
+            Ok(result) => Ok(PrepareRename::from_value(&result)),
+            Err(e) => {
+                warn!("Prepare rename request failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    pub async fn rename(
+        &self,
+        uri: String,
+        line: u64,
+        character: u64,
+        version: i64,
+        new_name: String,
+    ) -> Result<Option<WorkspaceEdit>, String> {
+        self.ensure_started().await?;
+
+        let params = serde_json::json!({
+            "textDocument": { "uri": uri, "version": version },
+            "position": { "line": line, "character": character },
+            "newName": new_name
+        });
+
+        match self.send_request("textDocument/rename", Some(params)).await {
             Ok(result) => {
                 if result.is_null() {
                     return Ok(None);
                 }
-                if let Ok(location) = serde_json::from_value::<Location>(result.clone()) {
-                    return Ok(Some(location));
-                } else if let Some(arr) = result.as_array() {
-                    if let Some(first) = arr.first() {
-                        if let Ok(location) = serde_json::from_value::<Location>(first.clone()) {
-                            return Ok(Some(location));
-                        }
-                    }
+                Ok(Some(WorkspaceEdit::from_value(&result)))
+            }
+            Err(e) => {
+                warn!("Rename request failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Requests `textDocument/formatting`, or `rangeFormatting` when `range` is given.
+    pub async fn format(
+        &self,
+        uri: String,
+        options: FormattingOptions,
+        range: Option<Range>,
+    ) -> Result<Vec<TextEdit>, String> {
+        self.ensure_started().await?;
+
+        let (method, params) = match range {
+            Some(range) => (
+                "textDocument/rangeFormatting",
+                serde_json::json!({
+                    "textDocument": { "uri": uri },
+                    "range": range,
+                    "options": options
+                }),
+            ),
+            None => (
+                "textDocument/formatting",
+                serde_json::json!({
+                    "textDocument": { "uri": uri },
+                    "options": options
+                }),
+            ),
+        };
+
+        match self.send_request(method, Some(params)).await {
+            Ok(result) => Ok(serde_json::from_value::<Vec<TextEdit>>(result).unwrap_or_default()),
+            Err(e) => {
+                warn!("Formatting request failed: {}", e);
+                Ok(vec![])
+            }
+        }
+    }
+
+    pub async fn document_symbols(&self, uri: String) -> Result<Vec<DocumentSymbol>, String> {
+        self.ensure_started().await?;
+
+        let params = serde_json::json!({
+            "textDocument": { "uri": uri }
+        });
+
+        match self.send_request("textDocument/documentSymbol", Some(params)).await {
+            Ok(result) => Ok(result
+                .as_array()
+                .map(|items| items.iter().filter_map(DocumentSymbol::from_value).collect())
+                .unwrap_or_default()),
+            Err(e) => {
+                warn!("Document symbol request failed: {}", e);
+                Ok(vec![])
+            }
+        }
+    }
+
+    /// Whether the server advertised `semanticTokensProvider` in its capabilities.
+    pub async fn supports_semantic_tokens(&self) -> bool {
+        self.capabilities.lock().await.get("semanticTokensProvider").is_some()
+    }
+
+    /// Requests `semanticTokens/full`, or `full/delta` when a previous result id is known.
+    pub async fn semantic_tokens(
+        &self,
+        uri: String,
+        previous_result_id: Option<String>,
+    ) -> Result<Option<SemanticTokens>, String> {
+        self.ensure_started().await?;
+
+        let (legend, supports_delta) = {
+            let capabilities = self.capabilities.lock().await;
+            let provider = &capabilities["semanticTokensProvider"];
+            let legend =
+                serde_json::from_value::<SemanticTokensLegend>(provider["legend"].clone()).unwrap_or_default();
+            let supports_delta = provider["full"]["delta"].as_bool().unwrap_or(false);
+            (legend, supports_delta)
+        };
+
+        let (method, params) = match previous_result_id {
+            Some(previous) if supports_delta => (
+                "textDocument/semanticTokens/full/delta",
+                serde_json::json!({
+                    "textDocument": { "uri": uri },
+                    "previousResultId": previous
+                }),
+            ),
+            _ => (
+                "textDocument/semanticTokens/full",
+                serde_json::json!({
+                    "textDocument": { "uri": uri }
+                }),
+            ),
+        };
+
+        match self.send_request(method, Some(params)).await {
+            Ok(result) => {
+                if result.is_null() {
+                    return Ok(None);
+                }
+                let result_id = result["resultId"].as_str().map(str::to_string);
+                if let Ok(edits) = serde_json::from_value::<Vec<SemanticTokensEdit>>(result["edits"].clone()) {
+                    return Ok(Some(SemanticTokens::Delta { result_id, legend, edits }));
+                }
+                if let Ok(data) = serde_json::from_value::<Vec<u32>>(result["data"].clone()) {
+                    return Ok(Some(SemanticTokens::Full { result_id, legend, data }));
                 }
                 Ok(None)
             }
             Err(e) => {
-                warn!("Goto definition request failed: {}", e);
+                warn!("Semantic tokens request failed: {}", e);
                 Ok(None)
             }
         }
     }
+
+    pub async fn signature_help(&self, uri: String, line: u64, character: u64, version: i64) -> Result<Option<SignatureHelp>, String> {
+        self.ensure_started().await?;
+
+        let params = serde_json::json!({
+            "textDocument": { "uri": uri, "version": version },
+            "position": { "line": line, "character": character }
+        });
+
+        match self.send_request("textDocument/signatureHelp", Some(params)).await {
+            Ok(result) => {
+                if result.is_null() {
+                    return Ok(None);
+                }
+                Ok(serde_json::from_value::<SignatureHelp>(result).ok())
+            }
+            Err(e) => {
+                warn!("Signature help request failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    pub async fn inlay_hints(&self, uri: String, range: Range) -> Result<Vec<InlayHint>, String> {
+        self.ensure_started().await?;
+
+        let params = serde_json::json!({
+            "textDocument": { "uri": uri },
+            "range": range
+        });
+
+        match self.send_request("textDocument/inlayHint", Some(params)).await {
+            Ok(result) => Ok(result
+                .as_array()
+                .map(|hints| hints.iter().filter_map(InlayHint::from_value).collect())
+                .unwrap_or_default()),
+            Err(e) => {
+                warn!("Inlay hint request failed: {}", e);
+                Ok(vec![])
+            }
+        }
+    }
+
+    /// Requests code actions for `range`, passing the diagnostics they should fix.
+    pub async fn code_actions(
+        &self,
+        uri: String,
+        range: Range,
+        diagnostics: Vec<Diagnostic>,
+        only: Option<Vec<String>>,
+    ) -> Result<Vec<CodeAction>, String> {
+        self.ensure_started().await?;
+
+        // Without diagnostics from the caller, use the ones the server published
+        let diagnostics = if diagnostics.is_empty() {
+            let published = self.diagnostics.lock().await;
+            published
+                .get(&uri)
+                .map(|all| all.iter().filter(|d| ranges_overlap(&d.range, &range)).cloned().collect())
+                .unwrap_or_default()
+        } else {
+            diagnostics
+        };
+
+        let mut context = serde_json::json!({ "diagnostics": diagnostics });
+        if let Some(only) = only {
+            context["only"] = serde_json::json!(only);
+        }
+
+        let params = serde_json::json!({
+            "textDocument": { "uri": uri },
+            "range": range,
+            "context": context
+        });
+
+        match self.send_request("textDocument/codeAction", Some(params)).await {
+            Ok(result) => Ok(result
+                .as_array()
+                .map(|actions| actions.iter().filter_map(CodeAction::from_value).collect())
+                .unwrap_or_default()),
+            Err(e) => {
+                warn!("Code action request failed: {}", e);
+                Ok(vec![])
+            }
+        }
+    }
+
+    pub async fn execute_command(&self, command: String, arguments: Vec<Value>) -> Result<Value, String> {
+        self.ensure_started().await?;
+
+        let params = serde_json::json!({
+            "command": command,
+            "arguments": arguments
+        });
+
+        self.send_request("workspace/executeCommand", Some(params)).await
+    }
 }
 
 impl Default for LspManager {
@@ -496,3 +1882,111 @@ pub async fn start_lsp_server(_lsp_manager: Arc<LspManager>) -> Result<(), Strin
     info!("LSP server manager initialized (lazy start)");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(line: u64, character: u64) -> Position {
+        Position { line, character }
+    }
+
+    fn insert(line: u64, character: u64, text: &str) -> TextEdit {
+        TextEdit {
+            range: Range { start: position(line, character), end: position(line, character) },
+            new_text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn position_to_offset_counts_utf16_units() {
+        // The emoji is one char, two UTF-16 units and four bytes
+        let text = "a😀b\nc";
+        assert_eq!(position_to_offset(text, &position(0, 1)), 1);
+        assert_eq!(position_to_offset(text, &position(0, 3)), 5);
+        assert_eq!(position_to_offset(text, &position(1, 0)), 7);
+        assert_eq!(position_to_offset(text, &position(1, 1)), 8);
+    }
+
+    #[test]
+    fn position_to_offset_clamps_to_the_line() {
+        let text = "ab\r\ncd";
+        assert_eq!(position_to_offset(text, &position(0, 10)), 2);
+        assert_eq!(position_to_offset(text, &position(5, 0)), text.len());
+    }
+
+    #[test]
+    fn apply_text_edits_in_any_order() {
+        let text = "let x = 1\nlet y = 2\n";
+        let edits = vec![
+            TextEdit {
+                range: Range { start: position(1, 4), end: position(1, 5) },
+                new_text: "second".to_string(),
+            },
+            TextEdit {
+                range: Range { start: position(0, 4), end: position(0, 5) },
+                new_text: "first".to_string(),
+            },
+        ];
+        assert_eq!(apply_text_edits(text, &edits), "let first = 1\nlet second = 2\n");
+    }
+
+    #[test]
+    fn apply_text_edits_keeps_order_of_inserts_at_one_position() {
+        let edits = vec![insert(0, 1, "x"), insert(0, 1, "y"), insert(0, 1, "z")];
+        assert_eq!(apply_text_edits("ab", &edits), "axyzb");
+    }
+
+    #[test]
+    fn apply_text_edits_after_non_bmp_characters() {
+        let edits = vec![TextEdit {
+            range: Range { start: position(0, 2), end: position(0, 3) },
+            new_text: "!".to_string(),
+        }];
+        assert_eq!(apply_text_edits("😀?", &edits), "😀!");
+    }
+
+    #[test]
+    fn workspace_edit_merges_changes_and_document_changes() {
+        let value = serde_json::json!({
+            "changes": {
+                "file:///a.typ": [
+                    { "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 1 } }, "newText": "x" }
+                ]
+            },
+            "documentChanges": [
+                {
+                    "textDocument": { "uri": "file:///a.typ", "version": 3 },
+                    "edits": [
+                        { "range": { "start": { "line": 1, "character": 0 }, "end": { "line": 1, "character": 0 } }, "newText": "y" }
+                    ]
+                },
+                {
+                    "textDocument": { "uri": "file:///b.typ", "version": null },
+                    "edits": []
+                },
+                { "kind": "create", "uri": "file:///c.typ" }
+            ]
+        });
+
+        let edit = WorkspaceEdit::from_value(&value);
+        assert_eq!(edit.changes.len(), 2);
+        let a = &edit.changes["file:///a.typ"];
+        assert_eq!(a.len(), 2);
+        assert_eq!(a[0].new_text, "x");
+        assert_eq!(a[1].new_text, "y");
+        assert!(edit.changes["file:///b.typ"].is_empty());
+        assert!(!edit.changes.contains_key("file:///c.typ"));
+    }
+
+    #[test]
+    fn semantic_tokens_legend_keeps_modifiers() {
+        let value = serde_json::json!({
+            "tokenTypes": ["keyword", "string"],
+            "tokenModifiers": ["strong", "emph"],
+        });
+        let legend: SemanticTokensLegend = serde_json::from_value(value).unwrap();
+        assert_eq!(legend.token_types, ["keyword", "string"]);
+        assert_eq!(legend.token_modifiers, ["strong", "emph"]);
+    }
+}
@@ -0,0 +1,248 @@
+use serde::Serialize;
+use typst::syntax::ast::{self, AstNode};
+use typst::syntax::{LinkedNode, Source, SyntaxKind};
+
+use crate::lsp::{DocumentSymbol, Position, Range};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutlineKind {
+    Heading,
+    Function,
+    Variable,
+    Label,
+    Other,
+}
+
+impl OutlineKind {
+    /// Maps the LSP `SymbolKind` values Tinymist uses for Typst symbols.
+    fn from_symbol_kind(kind: u64) -> Self {
+        match kind {
+            3 => OutlineKind::Heading,
+            6 | 12 => OutlineKind::Function,
+            13 => OutlineKind::Variable,
+            14 => OutlineKind::Label,
+            _ => OutlineKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutlineItem {
+    pub name: String,
+    pub kind: OutlineKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<usize>,
+    pub range: Range,
+    pub children: Vec<OutlineItem>,
+}
+
+impl From<DocumentSymbol> for OutlineItem {
+    fn from(symbol: DocumentSymbol) -> Self {
+        OutlineItem {
+            name: symbol.name,
+            kind: OutlineKind::from_symbol_kind(symbol.kind),
+            level: None,
+            range: symbol.range,
+            children: symbol.children.into_iter().map(OutlineItem::from).collect(),
+        }
+    }
+}
+
+/// Converts a byte offset into an LSP position (UTF-16 columns).
+pub fn byte_to_position(source: &Source, byte: usize) -> Position {
+    let lines = source.lines();
+    let line = lines.byte_to_line(byte).unwrap_or_else(|| lines.len_lines().saturating_sub(1));
+    let line_start = lines.line_to_byte(line).unwrap_or(0);
+    let character = source
+        .text()
+        .get(line_start..byte)
+        .map(|text| text.encode_utf16().count())
+        .unwrap_or(0);
+
+    Position {
+        line: line as u64,
+        character: character as u64,
+    }
+}
+
+fn node_range(source: &Source, node: &LinkedNode) -> Range {
+    let range = node.range();
+    Range {
+        start: byte_to_position(source, range.start),
+        end: byte_to_position(source, range.end),
+    }
+}
+
+/// Builds the outline by walking the syntax tree: headings nest by depth,
+/// `#let` bindings and labels attach to the heading they appear under.
+pub fn outline_from_source(source: &Source) -> Vec<OutlineItem> {
+    let mut entries = Vec::new();
+    collect_entries(source, &LinkedNode::new(source.root()), &mut entries);
+    nest_entries(entries)
+}
+
+fn collect_entries(source: &Source, node: &LinkedNode, entries: &mut Vec<(Option<usize>, OutlineItem)>) {
+    match node.kind() {
+        SyntaxKind::Heading => {
+            if let Some(heading) = node.cast::<ast::Heading>() {
+                let level = heading.depth().get();
+                let name = heading.body().to_untyped().clone().into_text().trim().to_string();
+                entries.push((
+                    Some(level),
+                    OutlineItem {
+                        name,
+                        kind: OutlineKind::Heading,
+                        level: Some(level),
+                        range: node_range(source, node),
+                        children: vec![],
+                    },
+                ));
+            }
+        }
+        SyntaxKind::LetBinding => {
+            if let Some(binding) = node.cast::<ast::LetBinding>() {
+                let (kind, names) = match binding.kind() {
+                    ast::LetBindingKind::Closure(ident) => {
+                        (OutlineKind::Function, vec![ident.get().to_string()])
+                    }
+                    ast::LetBindingKind::Normal(pattern) => (
+                        OutlineKind::Variable,
+                        pattern.bindings().iter().map(|ident| ident.get().to_string()).collect(),
+                    ),
+                };
+                for name in names {
+                    entries.push((
+                        None,
+                        OutlineItem {
+                            name,
+                            kind,
+                            level: None,
+                            range: node_range(source, node),
+                            children: vec![],
+                        },
+                    ));
+                }
+            }
+            // Bindings inside a definition's body are not part of the outline
+            return;
+        }
+        SyntaxKind::Label => {
+            if let Some(label) = node.cast::<ast::Label>() {
+                entries.push((
+                    None,
+                    OutlineItem {
+                        name: label.get().to_string(),
+                        kind: OutlineKind::Label,
+                        level: None,
+                        range: node_range(source, node),
+                        children: vec![],
+                    },
+                ));
+            }
+        }
+        _ => {}
+    }
+
+    for child in node.children() {
+        collect_entries(source, &child, entries);
+    }
+}
+
+fn nest_entries(entries: Vec<(Option<usize>, OutlineItem)>) -> Vec<OutlineItem> {
+    let mut roots = Vec::new();
+    let mut stack: Vec<(usize, OutlineItem)> = Vec::new();
+
+    fn close_top(stack: &mut Vec<(usize, OutlineItem)>, roots: &mut Vec<OutlineItem>) {
+        if let Some((_, item)) = stack.pop() {
+            match stack.last_mut() {
+                Some((_, parent)) => parent.children.push(item),
+                None => roots.push(item),
+            }
+        }
+    }
+
+    for (level, item) in entries {
+        match level {
+            Some(level) => {
+                while stack.last().is_some_and(|(open, _)| *open >= level) {
+                    close_top(&mut stack, &mut roots);
+                }
+                stack.push((level, item));
+            }
+            None => match stack.last_mut() {
+                Some((_, parent)) => parent.children.push(item),
+                None => roots.push(item),
+            },
+        }
+    }
+
+    while !stack.is_empty() {
+        close_top(&mut stack, &mut roots);
+    }
+
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(items: &[OutlineItem]) -> Vec<&str> {
+        items.iter().map(|item| item.name.as_str()).collect()
+    }
+
+    #[test]
+    fn headings_nest_by_level() {
+        let source = Source::detached("= One\n== Two\n== Three\n= Four\n");
+        let outline = outline_from_source(&source);
+        assert_eq!(names(&outline), ["One", "Four"]);
+        assert_eq!(names(&outline[0].children), ["Two", "Three"]);
+        assert!(outline[1].children.is_empty());
+    }
+
+    #[test]
+    fn skipped_levels_nest_under_the_closest_heading() {
+        let source = Source::detached("= One\n=== Deep\n== Two\n= Three\n");
+        let outline = outline_from_source(&source);
+        assert_eq!(names(&outline), ["One", "Three"]);
+        assert_eq!(names(&outline[0].children), ["Deep", "Two"]);
+        assert_eq!(outline[0].children[0].level, Some(3));
+    }
+
+    #[test]
+    fn a_deeper_first_heading_is_a_root() {
+        let source = Source::detached("== Two\n= One\n");
+        assert_eq!(names(&outline_from_source(&source)), ["Two", "One"]);
+    }
+
+    #[test]
+    fn bindings_attach_to_the_enclosing_heading() {
+        let source = Source::detached("#let top = 1\n= One\n#let f(x) = x\n");
+        let outline = outline_from_source(&source);
+        assert_eq!(names(&outline), ["top", "One"]);
+        assert_eq!(outline[0].kind, OutlineKind::Variable);
+        assert_eq!(names(&outline[1].children), ["f"]);
+        assert_eq!(outline[1].children[0].kind, OutlineKind::Function);
+    }
+
+    #[test]
+    fn positions_use_utf16_columns() {
+        let source = Source::detached("😀 = x\n= Two");
+        let emoji_end = "😀".len();
+        let position = byte_to_position(&source, emoji_end);
+        assert_eq!((position.line, position.character), (0, 2));
+
+        let heading = source.text().find("= Two").unwrap();
+        let position = byte_to_position(&source, heading);
+        assert_eq!((position.line, position.character), (1, 0));
+    }
+
+    #[test]
+    fn heading_ranges_span_the_heading() {
+        let source = Source::detached("😀\n== Two");
+        let outline = outline_from_source(&source);
+        assert_eq!((outline[0].range.start.line, outline[0].range.start.character), (1, 0));
+        assert_eq!((outline[0].range.end.line, outline[0].range.end.character), (1, 6));
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use typst::syntax::package::PackageVersion;
+use typst_kit::download::{Downloader, ProgressSink};
+use typst_kit::package::DEFAULT_REGISTRY;
+
+use crate::packages::USER_AGENT;
+
+/// A cached index younger than this is used without refetching.
+const INDEX_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The Typst Universe index for the `preview` namespace.
+pub fn default_index_url() -> String {
+    format!("{}/preview/index.json", DEFAULT_REGISTRY)
+}
+
+/// Name of the cache file for the index fetched from `source`, so switching
+/// index URLs never serves another source's cached index.
+pub fn cache_file_name(source: &str) -> String {
+    // FNV-1a: stable across builds, unlike `DefaultHasher`
+    let hash = source.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("package-index-{:016x}.json", hash)
+}
+
+/// One package version as listed in the registry's `index.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexEntry {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// Minimum compiler version, e.g. `0.12.0`.
+    #[serde(default)]
+    pub compiler: Option<String>,
+    #[serde(default)]
+    pub repository: Option<String>,
+    #[serde(default)]
+    pub homepage: Option<String>,
+    #[serde(default)]
+    pub template: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageVersionInfo {
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compiler: Option<String>,
+}
+
+/// A package with all its versions, described by its latest release.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageSummary {
+    pub name: String,
+    pub latest: String,
+    /// Newest first.
+    pub versions: Vec<PackageVersionInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub authors: Vec<String>,
+    pub keywords: Vec<String>,
+    pub categories: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    pub is_template: bool,
+}
+
+pub struct PackageIndex {
+    packages: Vec<PackageSummary>,
+}
+
+impl PackageIndex {
+    /// Loads the index from `cache_file` when it is fresh, otherwise fetches it
+    /// from `source` and refreshes the cache. `source` is an HTTP(S) URL, a
+    /// `file://` URL or a plain path. A stale cache is used if fetching fails.
+    pub fn load(source: &str, cache_file: &Path, force: bool) -> Result<Self, String> {
+        let fresh = std::fs::metadata(cache_file)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age < INDEX_MAX_AGE);
+
+        if fresh && !force {
+            if let Ok(index) = Self::read(cache_file) {
+                return Ok(index);
+            }
+        }
+
+        match fetch(source) {
+            Ok(bytes) => {
+                let index = Self::parse(&bytes)?;
+                if let Some(dir) = cache_file.parent() {
+                    let _ = std::fs::create_dir_all(dir);
+                }
+                if let Err(e) = std::fs::write(cache_file, &bytes) {
+                    tracing::warn!("Failed to cache package index: {}", e);
+                }
+                Ok(index)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to fetch package index from {}: {}", source, e);
+                Self::read(cache_file).map_err(|_| e)
+            }
+        }
+    }
+
+    fn read(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        Self::parse(&bytes)
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let entries: Vec<IndexEntry> = serde_json::from_slice(bytes)
+            .map_err(|e| format!("Invalid package index: {}", e))?;
+
+        let mut grouped: BTreeMap<String, Vec<IndexEntry>> = BTreeMap::new();
+        for entry in entries {
+            grouped.entry(entry.name.clone()).or_default().push(entry);
+        }
+
+        let packages = grouped
+            .into_values()
+            .filter_map(|mut versions| {
+                versions.sort_by_key(|entry| std::cmp::Reverse(parse_version(&entry.version)));
+                let latest = versions.first()?.clone();
+                Some(PackageSummary {
+                    name: latest.name,
+                    latest: latest.version,
+                    versions: versions
+                        .into_iter()
+                        .map(|entry| PackageVersionInfo {
+                            version: entry.version,
+                            compiler: entry.compiler,
+                        })
+                        .collect(),
+                    description: latest.description,
+                    authors: latest.authors,
+                    keywords: latest.keywords,
+                    categories: latest.categories,
+                    repository: latest.repository.or(latest.homepage),
+                    is_template: latest.template.is_some(),
+                })
+            })
+            .collect();
+
+        Ok(PackageIndex { packages })
+    }
+
+    /// Case-insensitive search over names, descriptions and keywords,
+    /// optionally restricted to a category. Name matches rank first.
+    pub fn search(&self, query: &str, category: Option<&str>) -> Vec<PackageSummary> {
+        let query = query.trim().to_lowercase();
+
+        let mut matches: Vec<(u8, &PackageSummary)> = self
+            .packages
+            .iter()
+            .filter(|package| category.is_none_or(|c| package.categories.iter().any(|pc| pc == c)))
+            .filter_map(|package| {
+                let name = package.name.to_lowercase();
+                let rank = if query.is_empty() || name == query {
+                    0
+                } else if name.contains(&query) {
+                    1
+                } else if package.keywords.iter().any(|k| k.to_lowercase().contains(&query)) {
+                    2
+                } else if package
+                    .description
+                    .as_ref()
+                    .is_some_and(|d| d.to_lowercase().contains(&query))
+                {
+                    3
+                } else {
+                    return None;
+                };
+                Some((rank, package))
+            })
+            .collect();
+
+        matches.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.name.cmp(&b.1.name)));
+        matches.into_iter().map(|(_, package)| package.clone()).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PackageSummary> {
+        self.packages.iter().find(|package| package.name == name)
+    }
+
+    /// All categories used in the index, sorted.
+    pub fn categories(&self) -> Vec<String> {
+        let mut categories: Vec<String> = self
+            .packages
+            .iter()
+            .flat_map(|package| package.categories.iter().cloned())
+            .collect();
+        categories.sort();
+        categories.dedup();
+        categories
+    }
+}
+
+fn parse_version(version: &str) -> Option<PackageVersion> {
+    PackageVersion::from_str(version).ok()
+}
+
+fn fetch(source: &str) -> Result<Vec<u8>, String> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        Downloader::new(USER_AGENT)
+            .download_with_progress(source, &mut ProgressSink)
+            .map_err(|e| e.to_string())
+    } else {
+        let path = source.strip_prefix("file://").unwrap_or(source);
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = r#"[
+        {
+            "name": "cetz",
+            "version": "0.2.2",
+            "description": "Drawing with Typst",
+            "keywords": ["canvas", "draw"],
+            "categories": ["visualization"],
+            "compiler": "0.11.0",
+            "homepage": "https://example.com/cetz"
+        },
+        {
+            "name": "cetz",
+            "version": "0.10.0",
+            "description": "Drawing with Typst, made easy",
+            "keywords": ["canvas", "draw"],
+            "categories": ["visualization"],
+            "repository": "https://github.com/cetz-package/cetz"
+        },
+        {
+            "name": "charged-ieee",
+            "version": "0.1.0",
+            "description": "An IEEE-style paper template",
+            "categories": ["paper"],
+            "template": { "path": "template", "entrypoint": "main.typ" }
+        },
+        {
+            "name": "tablex",
+            "version": "0.0.8",
+            "description": "More powerful tables, drawn on a canvas",
+            "keywords": ["table"],
+            "categories": ["layout"]
+        }
+    ]"#;
+
+    fn names(packages: &[PackageSummary]) -> Vec<&str> {
+        packages.iter().map(|package| package.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parse_groups_versions_newest_first() {
+        let index = PackageIndex::parse(FIXTURE.as_bytes()).unwrap();
+        let cetz = index.get("cetz").unwrap();
+        // 0.10.0 is newer than 0.2.2, which a string comparison would get wrong
+        assert_eq!(cetz.latest, "0.10.0");
+        let versions: Vec<&str> = cetz.versions.iter().map(|v| v.version.as_str()).collect();
+        assert_eq!(versions, ["0.10.0", "0.2.2"]);
+        assert_eq!(cetz.versions[1].compiler.as_deref(), Some("0.11.0"));
+        assert_eq!(cetz.description.as_deref(), Some("Drawing with Typst, made easy"));
+        assert_eq!(cetz.repository.as_deref(), Some("https://github.com/cetz-package/cetz"));
+        assert!(!cetz.is_template);
+        assert!(index.get("charged-ieee").unwrap().is_template);
+    }
+
+    #[test]
+    fn parse_rejects_invalid_json() {
+        assert!(PackageIndex::parse(b"{ not an index").is_err());
+    }
+
+    #[test]
+    fn search_ranks_name_matches_first() {
+        let index = PackageIndex::parse(FIXTURE.as_bytes()).unwrap();
+        // "tablex" matches "canvas" in its description, "cetz" in its keywords
+        assert_eq!(names(&index.search("canvas", None)), ["cetz", "tablex"]);
+        assert_eq!(names(&index.search("CETZ", None)), ["cetz"]);
+        assert_eq!(names(&index.search("tab", None)), ["tablex"]);
+        assert!(index.search("nothing like this", None).is_empty());
+    }
+
+    #[test]
+    fn search_filters_by_category() {
+        let index = PackageIndex::parse(FIXTURE.as_bytes()).unwrap();
+        assert_eq!(names(&index.search("", Some("paper"))), ["charged-ieee"]);
+        assert_eq!(names(&index.search("", None)), ["cetz", "charged-ieee", "tablex"]);
+        assert_eq!(index.categories(), ["layout", "paper", "visualization"]);
+    }
+
+    #[test]
+    fn load_reads_a_local_index_and_caches_it_per_source() {
+        let dir = std::env::temp_dir().join(format!("typst-editor-index-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("index.json");
+        std::fs::write(&source, FIXTURE).unwrap();
+        let source = source.to_string_lossy().to_string();
+
+        let cache_file = dir.join(cache_file_name(&source));
+        let index = PackageIndex::load(&source, &cache_file, false).unwrap();
+        assert_eq!(index.search("", None).len(), 3);
+        assert!(cache_file.is_file());
+        assert_ne!(cache_file_name(&source), cache_file_name(&default_index_url()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,523 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use typst::syntax::package::{PackageManifest, PackageSpec};
+use typst_kit::download::{DownloadState, Downloader, Progress, ProgressSink};
+use typst_kit::package::PackageStorage;
+
+use crate::settings::PackageSettings;
+
+pub(crate) const USER_AGENT: &str = "typst-editor/0.1.0";
+/// Minimum time between two progress reports for the same download.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadStatus {
+    Started,
+    Progress,
+    Done,
+    Failed,
+}
+
+/// Payload of `package-download` events.
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageDownload {
+    /// The package spec, e.g. `@preview/cetz:0.3.1`.
+    pub package: String,
+    pub status: DownloadStatus,
+    pub downloaded: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub type DownloadListener = Arc<dyn Fn(PackageDownload) + Send + Sync>;
+
+/// Forwards `typst-kit` download progress of one package to a listener.
+pub struct DownloadReporter {
+    package: String,
+    listener: DownloadListener,
+    last_report: Option<Instant>,
+    downloaded: usize,
+    total: Option<usize>,
+}
+
+impl DownloadReporter {
+    pub fn new(spec: &PackageSpec, listener: DownloadListener) -> Self {
+        Self {
+            package: spec.to_string(),
+            listener,
+            last_report: None,
+            downloaded: 0,
+            total: None,
+        }
+    }
+
+    fn report(&self, status: DownloadStatus, error: Option<String>) {
+        (self.listener)(PackageDownload {
+            package: self.package.clone(),
+            status,
+            downloaded: self.downloaded,
+            total: self.total,
+            error,
+        });
+    }
+
+    /// Reports the final outcome; `prepare_package` only signals completion of
+    /// the transfer, so failures (including extraction) are reported here.
+    pub fn finish(&self, result: &Result<PathBuf, String>) {
+        if let Err(e) = result {
+            self.report(DownloadStatus::Failed, Some(e.clone()));
+        }
+    }
+}
+
+impl Progress for DownloadReporter {
+    fn print_start(&mut self) {
+        self.report(DownloadStatus::Started, None);
+    }
+
+    fn print_progress(&mut self, state: &DownloadState) {
+        self.downloaded = state.total_downloaded;
+        self.total = state.content_len;
+
+        let due = self.last_report.is_none_or(|last| last.elapsed() >= PROGRESS_INTERVAL);
+        if due {
+            self.last_report = Some(Instant::now());
+            self.report(DownloadStatus::Progress, None);
+        }
+    }
+
+    fn print_finish(&mut self, state: &DownloadState) {
+        self.downloaded = state.total_downloaded;
+        self.total = state.content_len;
+        self.report(DownloadStatus::Done, None);
+    }
+}
+
+/// Where a package on disk comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageLocation {
+    /// Downloaded packages, safe to delete.
+    Cache,
+    /// Locally installed packages (e.g. `@local`), never deleted by the editor.
+    Data,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CachedPackage {
+    pub namespace: String,
+    pub name: String,
+    pub version: String,
+    pub path: String,
+    /// Total size of the package directory in bytes.
+    pub size: u64,
+    pub location: PackageLocation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchStatus {
+    Idle,
+    Pending,
+    Failed(String),
+}
+
+/// A failed download is retried by the first compile after this long.
+const RETRY_FAILED_AFTER: Duration = Duration::from_secs(30);
+
+enum Download {
+    Pending,
+    Failed { error: String, at: Instant },
+}
+
+/// Downloads packages on background threads so compiles never wait on the
+/// network. `on_ready` runs after each download finishes (or fails) to
+/// trigger a recompile.
+pub struct PackageFetcher {
+    /// Downloads in flight and recent failures; one lock so status reads are consistent.
+    downloads: Mutex<HashMap<PackageSpec, Download>>,
+    listener: Option<DownloadListener>,
+    on_ready: Box<dyn Fn(&PackageSpec) + Send + Sync>,
+}
+
+impl PackageFetcher {
+    pub fn new(
+        listener: Option<DownloadListener>,
+        on_ready: impl Fn(&PackageSpec) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            downloads: Mutex::new(HashMap::new()),
+            listener,
+            on_ready: Box::new(on_ready),
+        }
+    }
+
+    pub fn status(&self, spec: &PackageSpec) -> FetchStatus {
+        match self.downloads.lock().get(spec) {
+            Some(Download::Pending) => FetchStatus::Pending,
+            Some(Download::Failed { error, .. }) => FetchStatus::Failed(error.clone()),
+            None => FetchStatus::Idle,
+        }
+    }
+
+    /// Forgets failed downloads so they are retried right away instead of
+    /// after [`RETRY_FAILED_AFTER`].
+    pub fn clear_failures(&self) {
+        self.downloads.lock().retain(|_, download| matches!(download, Download::Pending));
+    }
+
+    /// Starts downloading `spec` unless it is already pending or failed
+    /// recently; recent failures are kept so the recompile after a failure
+    /// does not refetch in a loop.
+    pub fn fetch(self: &Arc<Self>, storage: Arc<PackageStorage>, spec: PackageSpec) {
+        {
+            let mut downloads = self.downloads.lock();
+            match downloads.get(&spec) {
+                Some(Download::Pending) => return,
+                Some(Download::Failed { at, .. }) if at.elapsed() < RETRY_FAILED_AFTER => return,
+                _ => {}
+            }
+            downloads.insert(spec.clone(), Download::Pending);
+        }
+
+        tracing::info!("Fetching package in background: {}", spec);
+        let fetcher = Arc::clone(self);
+        std::thread::spawn(move || {
+            let result = prepare(&storage, &spec, fetcher.listener.as_ref());
+
+            // Replacing `Pending` in one step means no reader sees the package as idle
+            let mut downloads = fetcher.downloads.lock();
+            match result {
+                Ok(_) => {
+                    downloads.remove(&spec);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to fetch package {}: {}", spec, e);
+                    downloads.insert(spec.clone(), Download::Failed { error: e, at: Instant::now() });
+                }
+            }
+            drop(downloads);
+
+            (fetcher.on_ready)(&spec);
+        });
+    }
+}
+
+/// Prepares (downloads if needed) a package, reporting progress to `listener`.
+pub fn prepare(
+    storage: &PackageStorage,
+    spec: &PackageSpec,
+    listener: Option<&DownloadListener>,
+) -> Result<PathBuf, String> {
+    match listener {
+        Some(listener) => {
+            let mut progress = DownloadReporter::new(spec, listener.clone());
+            let result = storage
+                .prepare_package(spec, &mut progress)
+                .map_err(|e| e.to_string());
+            progress.finish(&result);
+            result
+        }
+        None => storage
+            .prepare_package(spec, &mut ProgressSink)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+pub fn new_storage(settings: &PackageSettings) -> PackageStorage {
+    PackageStorage::new(
+        settings.package_cache_path.as_deref().map(expand_home),
+        settings.package_path.as_deref().map(expand_home),
+        Downloader::new(USER_AGENT),
+    )
+}
+
+/// Expands a leading `~` to the user's home directory.
+fn expand_home(path: &str) -> PathBuf {
+    let home = std::env::var_os(if cfg!(target_os = "windows") { "USERPROFILE" } else { "HOME" });
+    match (path.strip_prefix("~"), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            PathBuf::from(home).join(rest.trim_start_matches(['/', '\\']))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Resolves a package from a namespace mapped to a custom folder in the settings.
+pub fn find_mapped(settings: &PackageSettings, spec: &PackageSpec) -> Option<PathBuf> {
+    let folder = settings.namespaces.get(spec.namespace.as_str())?;
+    let dir = expand_home(folder)
+        .join(spec.name.as_str())
+        .join(spec.version.to_string());
+    dir.is_dir().then_some(dir)
+}
+
+/// Finds an already installed or cached package without touching the network.
+pub fn find_local(storage: &PackageStorage, spec: &PackageSpec) -> Option<PathBuf> {
+    [storage.package_path(), storage.package_cache_path()]
+        .into_iter()
+        .flatten()
+        .map(|dir| {
+            dir.join(spec.namespace.as_str())
+                .join(spec.name.as_str())
+                .join(spec.version.to_string())
+        })
+        .find(|dir| dir.is_dir())
+}
+
+/// Lists every package in the data and cache directories of `storage` and in
+/// the namespace folders configured in `settings`.
+pub fn list_cached(storage: &PackageStorage, settings: &PackageSettings) -> Vec<CachedPackage> {
+    let mut packages = Vec::new();
+    let roots = [
+        (storage.package_path(), PackageLocation::Data),
+        (storage.package_cache_path(), PackageLocation::Cache),
+    ];
+
+    for (root, location) in roots {
+        let Some(root) = root else { continue };
+        for namespace in subdirs(root) {
+            list_namespace(&mut packages, &file_name(&namespace), &namespace, location);
+        }
+    }
+
+    for (namespace, folder) in &settings.namespaces {
+        list_namespace(&mut packages, namespace, &expand_home(folder), PackageLocation::Data);
+    }
+
+    packages.sort_by(|a, b| {
+        (&a.namespace, &a.name, &a.version).cmp(&(&b.namespace, &b.name, &b.version))
+    });
+    packages
+}
+
+/// Removes a downloaded package from the cache directory, along with the
+/// package and namespace directories if they end up empty.
+pub fn delete_cached(storage: &PackageStorage, namespace: &str, name: &str, version: &str) -> Result<(), String> {
+    let cache = storage
+        .package_cache_path()
+        .ok_or("No package cache directory")?;
+
+    for part in [namespace, name, version] {
+        if part.is_empty() || part.contains(['/', '\\']) || part == "." || part == ".." {
+            return Err(format!("Invalid package component: {:?}", part));
+        }
+    }
+
+    let dir = cache.join(namespace).join(name).join(version);
+    if !dir.is_dir() {
+        return Err(format!("Package @{}/{}:{} is not cached", namespace, name, version));
+    }
+
+    std::fs::remove_dir_all(&dir)
+        .map_err(|e| format!("Failed to delete {}: {}", dir.display(), e))?;
+
+    // Only succeeds for empty directories
+    let _ = std::fs::remove_dir(cache.join(namespace).join(name));
+    let _ = std::fs::remove_dir(cache.join(namespace));
+    Ok(())
+}
+
+/// Copies the template of package `spec` into `dest`, which must not exist or
+/// be empty, and returns the path of the template's entrypoint.
+pub fn create_from_template(
+    storage: &PackageStorage,
+    settings: &PackageSettings,
+    spec: &PackageSpec,
+    dest: &Path,
+    listener: Option<&DownloadListener>,
+) -> Result<PathBuf, String> {
+    let package_dir = match find_mapped(settings, spec) {
+        Some(dir) => dir,
+        None if settings.offline => find_local(storage, spec)
+            .ok_or_else(|| format!("Package {} is not cached (offline mode)", spec))?,
+        None => prepare(storage, spec, listener)?,
+    };
+
+    let manifest_path = package_dir.join("typst.toml");
+    let manifest = std::fs::read_to_string(&manifest_path)
+        .map_err(|e| format!("Failed to read {}: {}", manifest_path.display(), e))?;
+    let manifest: PackageManifest =
+        toml::from_str(&manifest).map_err(|e| format!("Invalid typst.toml: {}", e))?;
+    manifest.validate(spec).map_err(|e| e.to_string())?;
+
+    let template = manifest
+        .template
+        .ok_or_else(|| format!("Package {} is not a template", spec))?;
+
+    let occupied = std::fs::read_dir(dest)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+    if occupied {
+        return Err(format!("Directory {} is not empty", dest.display()));
+    }
+
+    let template_dir = package_relative(template.path.as_str(), "template path")?;
+    let entrypoint = package_relative(template.entrypoint.as_str(), "template entrypoint")?;
+
+    copy_dir(&package_dir.join(template_dir), dest)?;
+    Ok(dest.join(entrypoint))
+}
+
+/// Checks that a path from a package manifest stays inside the directory it
+/// is joined to: no root, drive prefix or `..` components.
+fn package_relative<'a>(path: &'a str, what: &str) -> Result<&'a Path, String> {
+    let path = Path::new(path);
+    let contained = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if !contained {
+        return Err(format!("Invalid {} in typst.toml: {}", what, path.display()));
+    }
+    Ok(path)
+}
+
+fn copy_dir(src: &Path, dest: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(dest)
+        .map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+    let entries = std::fs::read_dir(src)
+        .map_err(|e| format!("Failed to read {}: {}", src.display(), e))?;
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let target = dest.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)
+                .map_err(|e| format!("Failed to copy to {}: {}", target.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+fn list_namespace(packages: &mut Vec<CachedPackage>, namespace: &str, dir: &Path, location: PackageLocation) {
+    for name in subdirs(dir) {
+        for version in subdirs(&name) {
+            packages.push(CachedPackage {
+                namespace: namespace.to_string(),
+                name: file_name(&name),
+                version: file_name(&version),
+                path: version.display().to_string(),
+                size: dir_size(&version),
+                location,
+            });
+        }
+    }
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_relative_accepts_paths_inside_the_package() {
+        assert_eq!(package_relative("template", "path"), Ok(Path::new("template")));
+        assert_eq!(package_relative("./template/main.typ", "path"), Ok(Path::new("./template/main.typ")));
+    }
+
+    #[test]
+    fn package_relative_rejects_escapes() {
+        for path in ["..", "../outside", "template/../../outside", "template/../main.typ", "/etc/passwd"] {
+            assert!(package_relative(path, "path").is_err(), "{} was accepted", path);
+        }
+    }
+
+    /// Creates a template package `@test/tpl:0.1.0` whose template lives at
+    /// `template_path`, and returns the folder mapped to the `test` namespace.
+    fn template_package(root: &Path, template_path: &str) -> PathBuf {
+        let namespace = root.join("packages");
+        let package = namespace.join("tpl").join("0.1.0");
+        std::fs::create_dir_all(package.join("template")).unwrap();
+        std::fs::write(package.join("template").join("main.typ"), "= Title").unwrap();
+        std::fs::write(package.join("lib.typ"), "").unwrap();
+        std::fs::write(
+            package.join("typst.toml"),
+            format!(
+                "[package]\nname = \"tpl\"\nversion = \"0.1.0\"\nentrypoint = \"lib.typ\"\n\n\
+                 [template]\npath = \"{}\"\nentrypoint = \"main.typ\"\n",
+                template_path
+            ),
+        )
+        .unwrap();
+        namespace
+    }
+
+    fn create(root: &Path, template_path: &str) -> Result<PathBuf, String> {
+        let namespace = template_package(root, template_path);
+        let settings = PackageSettings {
+            offline: true,
+            namespaces: HashMap::from([("test".to_string(), namespace.display().to_string())]),
+            ..Default::default()
+        };
+        let spec: PackageSpec = "@test/tpl:0.1.0".parse().unwrap();
+        create_from_template(&new_storage(&settings), &settings, &spec, &root.join("project"), None)
+    }
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("typst-editor-template-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        root
+    }
+
+    #[test]
+    fn create_from_template_copies_the_template() {
+        let root = temp_root("copy");
+        let entrypoint = create(&root, "template").unwrap();
+        assert_eq!(entrypoint, root.join("project").join("main.typ"));
+        assert_eq!(std::fs::read_to_string(&entrypoint).unwrap(), "= Title");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn create_from_template_rejects_paths_outside_the_package() {
+        let root = temp_root("escape");
+        let outside = root.join("outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("secret.typ"), "").unwrap();
+
+        let absolute = outside.display().to_string();
+        for path in ["../../../outside", "template/../..", absolute.as_str()] {
+            assert!(create(&root, path).is_err(), "{} was accepted", path);
+            assert!(!root.join("project").exists());
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
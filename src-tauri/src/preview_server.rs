@@ -0,0 +1,266 @@
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::json;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, watch};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::PagePatch;
+
+pub const DEFAULT_PORT: u16 = 23630;
+const PREVIEW_PAGE: &str = include_str!("preview.html");
+/// Updates buffered per client; slower clients get a fresh snapshot instead.
+const UPDATE_BUFFER: usize = 64;
+/// Longest request head accepted before the connection is dropped.
+const MAX_REQUEST_HEAD: usize = 16 * 1024;
+
+/// Serves the live preview to browsers: `GET /` returns a viewer page that
+/// connects back over WebSocket and receives the same page patches as the
+/// editor, starting with a snapshot of the whole document.
+pub struct PreviewServer {
+    url: String,
+    /// The full current document, each page holding all its blocks.
+    pages: Mutex<Vec<PagePatch>>,
+    revision: Mutex<u64>,
+    updates: broadcast::Sender<String>,
+    shutdown: watch::Sender<bool>,
+}
+
+impl PreviewServer {
+    /// Binds to `port` on localhost, or on all interfaces when `lan` is set.
+    pub async fn start(port: u16, lan: bool) -> Result<Arc<Self>, String> {
+        let host = if lan { Ipv4Addr::UNSPECIFIED } else { Ipv4Addr::LOCALHOST };
+        let listener = TcpListener::bind(SocketAddr::from((host, port)))
+            .await
+            .map_err(|e| format!("Failed to bind preview server to port {}: {}", port, e))?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+
+        let url_host = if lan { lan_address().unwrap_or(Ipv4Addr::LOCALHOST) } else { Ipv4Addr::LOCALHOST };
+        let (updates, _) = broadcast::channel(UPDATE_BUFFER);
+        let (shutdown, _) = watch::channel(false);
+        let server = Arc::new(Self {
+            url: format!("http://{}:{}/", url_host, port),
+            pages: Mutex::new(Vec::new()),
+            revision: Mutex::new(0),
+            updates,
+            shutdown,
+        });
+        tracing::info!("Preview server listening on {}", server.url);
+
+        let accept_server = Arc::clone(&server);
+        tauri::async_runtime::spawn(async move { accept_server.accept_loop(listener).await });
+        Ok(server)
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Stops accepting connections and closes all open ones.
+    pub fn stop(&self) {
+        let _ = self.shutdown.send(true);
+    }
+
+    /// Applies a compile result to the mirrored document and forwards it to clients.
+    pub fn publish(&self, revision: u64, patches: &[PagePatch], total_pages: usize) {
+        if let (Ok(mut pages), Ok(mut last)) = (self.pages.lock(), self.revision.lock()) {
+            pages.truncate(total_pages);
+            for patch in patches {
+                merge_patch(&mut pages, patch);
+            }
+            *last = revision;
+        }
+
+        let message = json!({
+            "type": "patch",
+            "revision": revision,
+            "pages": patches,
+            "total_pages": total_pages,
+        });
+        let _ = self.updates.send(message.to_string());
+    }
+
+    pub fn publish_error(&self, revision: u64, message: &str) {
+        let message = json!({ "type": "error", "revision": revision, "message": message });
+        let _ = self.updates.send(message.to_string());
+    }
+
+    fn snapshot(&self) -> String {
+        let pages = self.pages.lock().map(|pages| pages.clone()).unwrap_or_default();
+        let revision = self.revision.lock().map(|revision| *revision).unwrap_or(0);
+        json!({
+            "type": "snapshot",
+            "revision": revision,
+            "total_pages": pages.len(),
+            "pages": pages,
+        })
+        .to_string()
+    }
+
+    async fn accept_loop(self: Arc<Self>, listener: TcpListener) {
+        let mut shutdown = self.shutdown.subscribe();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        tracing::debug!("Preview connection from {}", peer);
+                        let server = Arc::clone(&self);
+                        tauri::async_runtime::spawn(async move {
+                            if let Err(e) = server.handle(stream).await {
+                                tracing::debug!("Preview connection from {} closed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => tracing::warn!("Preview server accept failed: {}", e),
+                },
+                _ = shutdown.changed() => break,
+            }
+        }
+        tracing::info!("Preview server on {} stopped", self.url);
+    }
+
+    async fn handle(&self, mut stream: TcpStream) -> Result<(), String> {
+        let received = read_request_head(&mut stream).await?;
+        let head = String::from_utf8_lossy(&received).to_ascii_lowercase();
+
+        if is_websocket_upgrade(&head) {
+            // The handshake reads the request again, so replay what was consumed
+            let (read, write) = stream.into_split();
+            let stream = tokio::io::join(std::io::Cursor::new(received).chain(read), write);
+            return self.stream_updates(stream).await;
+        }
+
+        let response = if head.starts_with("get / ") || head.starts_with("get /index.html ") {
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                PREVIEW_PAGE.len(),
+                PREVIEW_PAGE
+            )
+        } else {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        };
+        stream.write_all(response.as_bytes()).await.map_err(|e| e.to_string())?;
+        stream.shutdown().await.map_err(|e| e.to_string())
+    }
+
+    async fn stream_updates<S>(&self, stream: S) -> Result<(), String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let socket = tokio_tungstenite::accept_async(stream)
+            .await
+            .map_err(|e| format!("WebSocket handshake failed: {}", e))?;
+        let (mut sink, mut incoming) = socket.split();
+
+        // Subscribe before taking the snapshot so no update falls in between
+        let mut updates = self.updates.subscribe();
+        let mut shutdown = self.shutdown.subscribe();
+        sink.send(Message::Text(self.snapshot().into()))
+            .await
+            .map_err(|e| e.to_string())?;
+
+        loop {
+            tokio::select! {
+                update = updates.recv() => {
+                    let text = match update {
+                        Ok(text) => text,
+                        Err(broadcast::error::RecvError::Lagged(_)) => self.snapshot(),
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    sink.send(Message::Text(text.into())).await.map_err(|e| e.to_string())?;
+                }
+                message = incoming.next() => match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    _ => {}
+                },
+                _ = shutdown.changed() => break,
+            }
+        }
+
+        let _ = sink.send(Message::Close(None)).await;
+        Ok(())
+    }
+}
+
+/// Reads until the blank line ending the request head and returns all bytes
+/// read, which may include the start of a body.
+async fn read_request_head(stream: &mut TcpStream) -> Result<Vec<u8>, String> {
+    let mut received = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !received.windows(4).any(|window| window == b"\r\n\r\n") {
+        if received.len() > MAX_REQUEST_HEAD {
+            return Err("Request head too large".to_string());
+        }
+        let n = stream.read(&mut buf).await.map_err(|e| e.to_string())?;
+        if n == 0 {
+            return Err("Connection closed before the end of the request head".to_string());
+        }
+        received.extend_from_slice(&buf[..n]);
+    }
+    Ok(received)
+}
+
+/// Whether the lowercased request `head` asks to upgrade to a WebSocket.
+fn is_websocket_upgrade(head: &str) -> bool {
+    let mut headers = head.lines().skip(1).take_while(|line| !line.trim_end().is_empty());
+    headers.any(|line| match line.split_once(':') {
+        Some((name, value)) => {
+            name.trim() == "upgrade" && value.split(',').any(|protocol| protocol.trim() == "websocket")
+        }
+        None => false,
+    })
+}
+
+/// Applies one page patch to the full page list, like the editor's `mergePatch`.
+fn merge_patch(pages: &mut Vec<PagePatch>, patch: &PagePatch) {
+    while pages.len() <= patch.page_index {
+        let index = pages.len();
+        pages.push(PagePatch {
+            page_index: index,
+            page_hash: 0,
+            page_size: patch.page_size,
+            blocks: Vec::new(),
+            removed_blocks: Vec::new(),
+        });
+    }
+
+    let page = &mut pages[patch.page_index];
+    page.page_hash = patch.page_hash;
+    page.page_size = patch.page_size;
+    page.blocks
+        .retain(|block| !patch.removed_blocks.contains(&block.block_id));
+    for block in &patch.blocks {
+        match page.blocks.iter_mut().find(|b| b.block_id == block.block_id) {
+            Some(existing) => *existing = block.clone(),
+            None => page.blocks.push(block.clone()),
+        }
+    }
+}
+
+/// The address other machines on the LAN reach us at. Connecting a UDP
+/// socket only selects a route; nothing is sent.
+fn lan_address() -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 80)).ok()?;
+    match socket.local_addr().ok()?.ip() {
+        std::net::IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn websocket_upgrade_is_read_from_the_headers() {
+        let upgrade = "get /ws http/1.1\r\nhost: localhost\r\nupgrade: websocket\r\nconnection: upgrade\r\n\r\n";
+        assert!(is_websocket_upgrade(upgrade));
+        assert!(is_websocket_upgrade("get / http/1.1\r\nupgrade:  h2c, websocket\r\n\r\n"));
+        assert!(!is_websocket_upgrade("get /?upgrade: websocket http/1.1\r\nhost: localhost\r\n\r\n"));
+        assert!(!is_websocket_upgrade("post / http/1.1\r\ncontent-length: 18\r\n\r\nupgrade: websocket"));
+    }
+}
@@ -0,0 +1,463 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, watch};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::export::CompileDiagnostic;
+use crate::{build_patch_internal, download_listener, emit_compile_result, export, resolve_inputs, AppState, PagePatch};
+
+/// Written to the app config directory while the server runs so local tools
+/// can find the port and token.
+const CONNECTION_FILE: &str = "remote.json";
+const NOTIFICATION_BUFFER: usize = 64;
+/// The LSP document the editor keeps its text in, as in `TypstLsp.ts`.
+const EDITOR_DOCUMENT_URI: &str = "file:///workspace/main.typ";
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// Contents of the connection file.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteInfo {
+    pub port: u16,
+    pub token: String,
+    /// Where this information is stored on disk.
+    pub connection_file: String,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl From<String> for RpcError {
+    fn from(message: String) -> Self {
+        Self::new(SERVER_ERROR, message)
+    }
+}
+
+/// JSON-RPC 2.0 over a localhost WebSocket for driving the editor from
+/// scripts and other editors. Clients authenticate with the token from the
+/// connection file, either as `?token=` in the URL or as a bearer token.
+///
+/// Methods: `openFile {path}`, `setText {text}`, `compile`, `exportPdf {output}`
+/// and `subscribe`/`unsubscribe`, which toggle `diagnostics` notifications
+/// sent after every compile.
+pub struct RemoteServer {
+    info: RemoteInfo,
+    notifications: broadcast::Sender<String>,
+    shutdown: watch::Sender<bool>,
+}
+
+impl RemoteServer {
+    pub async fn start(app: AppHandle, port: u16) -> Result<Arc<Self>, String> {
+        let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+            .await
+            .map_err(|e| format!("Failed to bind remote control to port {}: {}", port, e))?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+
+        let connection_file = app
+            .path()
+            .app_config_dir()
+            .map_err(|e| format!("Failed to resolve config directory: {}", e))?
+            .join(CONNECTION_FILE);
+        let info = RemoteInfo {
+            port,
+            token: random_token()?,
+            connection_file: connection_file.display().to_string(),
+        };
+        write_private(&connection_file, &serde_json::to_string_pretty(&info).map_err(|e| e.to_string())?)?;
+
+        let (notifications, _) = broadcast::channel(NOTIFICATION_BUFFER);
+        let (shutdown, _) = watch::channel(false);
+        let server = Arc::new(Self { info, notifications, shutdown });
+        tracing::info!("Remote control listening on 127.0.0.1:{}", port);
+
+        let accept_server = Arc::clone(&server);
+        tauri::async_runtime::spawn(async move { accept_server.accept_loop(app, listener).await });
+        Ok(server)
+    }
+
+    pub fn info(&self) -> &RemoteInfo {
+        &self.info
+    }
+
+    /// Closes all connections and removes the connection file.
+    pub fn stop(&self) {
+        let _ = self.shutdown.send(true);
+        let _ = std::fs::remove_file(&self.info.connection_file);
+    }
+
+    /// Sends a `diagnostics` notification to subscribed clients.
+    pub fn publish_diagnostics(
+        &self,
+        revision: u64,
+        result: &Result<(Vec<PagePatch>, usize), String>,
+        diagnostics: &[CompileDiagnostic],
+    ) {
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "diagnostics",
+            "params": compile_summary(revision, result, diagnostics),
+        });
+        let _ = self.notifications.send(notification.to_string());
+    }
+
+    async fn accept_loop(self: Arc<Self>, app: AppHandle, listener: TcpListener) {
+        let mut shutdown = self.shutdown.subscribe();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        let server = Arc::clone(&self);
+                        let app = app.clone();
+                        tauri::async_runtime::spawn(async move {
+                            if let Err(e) = server.handle(app, stream).await {
+                                tracing::debug!("Remote control connection closed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => tracing::warn!("Remote control accept failed: {}", e),
+                },
+                _ = shutdown.changed() => break,
+            }
+        }
+    }
+
+    async fn handle(&self, app: AppHandle, stream: TcpStream) -> Result<(), String> {
+        let token = self.info.token.clone();
+        let authenticate = move |request: &Request, response: Response| {
+            let valid = request_token(request).is_some_and(|presented| tokens_match(&presented, &token));
+            if valid {
+                Ok(response)
+            } else {
+                let mut error = ErrorResponse::new(Some("Invalid or missing token".to_string()));
+                *error.status_mut() = StatusCode::UNAUTHORIZED;
+                Err(error)
+            }
+        };
+        let socket = tokio_tungstenite::accept_hdr_async(stream, authenticate)
+            .await
+            .map_err(|e| format!("WebSocket handshake failed: {}", e))?;
+        let (mut sink, mut incoming) = socket.split();
+
+        let mut notifications = self.notifications.subscribe();
+        let mut shutdown = self.shutdown.subscribe();
+        let mut subscribed = false;
+
+        loop {
+            tokio::select! {
+                notification = notifications.recv() => match notification {
+                    Ok(text) if subscribed => {
+                        sink.send(Message::Text(text.into())).await.map_err(|e| e.to_string())?;
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                message = incoming.next() => {
+                    let text = match message {
+                        Some(Ok(Message::Text(text))) => text.to_string(),
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => continue,
+                    };
+                    if let Some(response) = self.respond(&app, &text, &mut subscribed).await {
+                        sink.send(Message::Text(response.to_string().into()))
+                            .await
+                            .map_err(|e| e.to_string())?;
+                    }
+                }
+                _ = shutdown.changed() => break,
+            }
+        }
+
+        let _ = sink.send(Message::Close(None)).await;
+        Ok(())
+    }
+
+    /// Handles one JSON-RPC message; returns `None` for notifications.
+    async fn respond(&self, app: &AppHandle, text: &str, subscribed: &mut bool) -> Option<Value> {
+        let request: Value = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => return Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()))),
+        };
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return Some(error_response(id.unwrap_or(Value::Null), RpcError::new(INVALID_REQUEST, "Missing method")));
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = match method {
+            "subscribe" => {
+                *subscribed = true;
+                Ok(Value::Null)
+            }
+            "unsubscribe" => {
+                *subscribed = false;
+                Ok(Value::Null)
+            }
+            _ => call(app, method, params).await,
+        };
+
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => error_response(id, error),
+        })
+    }
+}
+
+async fn call(app: &AppHandle, method: &str, params: Value) -> Result<Value, RpcError> {
+    let app = app.clone();
+    let state = Arc::clone(app.state::<Arc<AppState>>().inner());
+    let method = method_name(method)?;
+
+    let run = move || -> Result<Value, RpcError> {
+        match method {
+            Method::OpenFile => {
+                let path = string_param(&params, "path")?;
+                let content = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+                *state.current_file_path.lock().map_err(|e| e.to_string())? = Some(PathBuf::from(&path));
+                // The editor only loads the file; the compile happens here
+                let (revision, summary) = compile(&app, &state, &content)?;
+                app.emit("remote-open-file", json!({ "path": path, "revision": revision }))
+                    .map_err(|e| e.to_string())?;
+                Ok(summary)
+            }
+            Method::SetText => {
+                let text = string_param(&params, "text")?;
+                let (revision, summary) = compile(&app, &state, &text)?;
+                // The editor continues its document versions after this one
+                let manager = Arc::clone(&state.lsp_manager);
+                let version = tauri::async_runtime::block_on(
+                    manager.replace_text(EDITOR_DOCUMENT_URI.to_string(), text.clone()),
+                )
+                .map_err(|e| tracing::warn!("Failed to sync remote text with the LSP server: {}", e))
+                .ok();
+                app.emit("remote-set-text", json!({ "text": text, "revision": revision, "version": version }))
+                    .map_err(|e| e.to_string())?;
+                Ok(summary)
+            }
+            Method::Compile => {
+                let content = current_content(&state)?;
+                Ok(compile(&app, &state, &content)?.1)
+            }
+            Method::ExportPdf => {
+                let output = string_param(&params, "output")?;
+                let content = current_content(&state)?;
+                let file_path = state.current_file_path.lock().map_err(|e| e.to_string())?.clone();
+                let settings = state.settings.lock().map_err(|e| e.to_string())?.clone();
+                let inputs = resolve_inputs(&state, file_path.as_deref())?;
+
+                let file_path = file_path.map(|path| path.to_string_lossy().to_string());
+                let pdf = export::compile_to_pdf(
+                    &content,
+                    file_path.as_deref(),
+                    &settings,
+                    inputs,
+                    Some(download_listener(app.clone())),
+                )?;
+                std::fs::write(&output, pdf).map_err(|e| format!("Failed to write PDF: {}", e))?;
+                Ok(json!({ "path": output }))
+            }
+        }
+    };
+
+    tokio::task::spawn_blocking(run)
+        .await
+        .map_err(|e| RpcError::from(e.to_string()))?
+}
+
+enum Method {
+    OpenFile,
+    SetText,
+    Compile,
+    ExportPdf,
+}
+
+fn method_name(method: &str) -> Result<Method, RpcError> {
+    match method {
+        "openFile" => Ok(Method::OpenFile),
+        "setText" => Ok(Method::SetText),
+        "compile" => Ok(Method::Compile),
+        "exportPdf" => Ok(Method::ExportPdf),
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+    }
+}
+
+/// Compiles `content` as the current document under a new revision, updating
+/// the editor preview. Returns the revision and the compile summary.
+fn compile(app: &AppHandle, state: &AppState, content: &str) -> Result<(u64, Value), String> {
+    let revision = {
+        let mut last = state.last_revision.lock().map_err(|e| e.to_string())?;
+        *last += 1;
+        *last
+    };
+    let mut diagnostics = Vec::new();
+    let result = build_patch_internal(content, state, &mut diagnostics);
+    let summary = compile_summary(revision, &result, &diagnostics);
+    emit_compile_result(app, state, revision, result, &diagnostics)?;
+    Ok((revision, summary))
+}
+
+fn current_content(state: &AppState) -> Result<String, String> {
+    let world = state.world.lock().map_err(|e| e.to_string())?;
+    world
+        .as_ref()
+        .map(|world| world.snapshot_source().text().to_string())
+        .ok_or_else(|| "No document has been compiled yet".to_string())
+}
+
+/// The result of a compile for clients: success, the page count and all
+/// errors and warnings with their paths and LSP ranges.
+fn compile_summary(
+    revision: u64,
+    result: &Result<(Vec<PagePatch>, usize), String>,
+    diagnostics: &[CompileDiagnostic],
+) -> Value {
+    let mut summary = json!({
+        "revision": revision,
+        "success": result.is_ok(),
+        "diagnostics": diagnostics,
+    });
+    if let Ok((_, total_pages)) = result {
+        summary["totalPages"] = json!(total_pages);
+    }
+    summary
+}
+
+fn string_param(params: &Value, name: &str) -> Result<String, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Missing string parameter: {}", name)))
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+/// The token from `?token=` or an `Authorization: Bearer` header.
+fn request_token(request: &Request) -> Option<String> {
+    let from_query = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+            .map(str::to_string)
+    });
+    from_query.or_else(|| {
+        let header = request.headers().get("authorization")?.to_str().ok()?;
+        header.strip_prefix("Bearer ").map(|token| token.trim().to_string())
+    })
+}
+
+/// Compares tokens in time independent of where they first differ.
+fn tokens_match(presented: &str, expected: &str) -> bool {
+    let (presented, expected) = (presented.as_bytes(), expected.as_bytes());
+    presented.len() == expected.len()
+        && presented.iter().zip(expected).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// 128 bits from the OS random number generator, as hex.
+fn random_token() -> Result<String, String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("Failed to generate token: {}", e))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Writes `content` readable only by the current user where supported.
+fn write_private(path: &Path, content: &str) -> Result<(), String> {
+    use std::io::Write;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+
+    // The mode only applies to new files
+    let _ = std::fs::remove_file(path);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    file.write_all(content.as_bytes())
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str, authorization: Option<&str>) -> Request {
+        let mut builder = Request::builder().uri(uri);
+        if let Some(value) = authorization {
+            builder = builder.header("Authorization", value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    fn authenticates(request: &Request, expected: &str) -> bool {
+        request_token(request).is_some_and(|presented| tokens_match(&presented, expected))
+    }
+
+    #[test]
+    fn query_token_authenticates() {
+        let request = request("/?client=cli&token=secret", None);
+        assert_eq!(request_token(&request).as_deref(), Some("secret"));
+        assert!(authenticates(&request, "secret"));
+    }
+
+    #[test]
+    fn bearer_token_authenticates() {
+        let request = request("/", Some("Bearer secret "));
+        assert_eq!(request_token(&request).as_deref(), Some("secret"));
+        assert!(authenticates(&request, "secret"));
+    }
+
+    #[test]
+    fn wrong_token_is_rejected() {
+        assert!(!authenticates(&request("/?token=secreT", None), "secret"));
+        assert!(!authenticates(&request("/?token=secret2", None), "secret"));
+        assert!(!authenticates(&request("/", Some("Bearer other")), "secret"));
+        assert!(!authenticates(&request("/", Some("Basic secret")), "secret"));
+    }
+
+    #[test]
+    fn missing_token_is_rejected() {
+        let request = request("/?client=cli", None);
+        assert_eq!(request_token(&request), None);
+        assert!(!authenticates(&request, "secret"));
+        assert!(!tokens_match("", "secret"));
+    }
+}
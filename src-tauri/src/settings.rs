@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Manager};
+
+const SETTINGS_FILE: &str = "settings.json";
+/// Project file with default `sys.inputs`, placed next to the main file.
+const INPUTS_FILE: &str = "typst-inputs.toml";
+
+/// How the Tinymist language server is launched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LspSettings {
+    /// Explicit server executable; looked up in PATH and `~/.cargo/bin` when unset.
+    pub server_path: Option<String>,
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+    /// Passed as `initializationOptions` in the `initialize` request.
+    pub initialization_options: Option<Value>,
+}
+
+impl Default for LspSettings {
+    fn default() -> Self {
+        Self {
+            server_path: None,
+            args: vec!["lsp".to_string()],
+            env: HashMap::new(),
+            initialization_options: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FormatSettings {
+    /// Format through the language server before `save_document` writes the file.
+    pub on_save: bool,
+    pub tab_size: u32,
+    pub insert_spaces: bool,
+}
+
+impl Default for FormatSettings {
+    fn default() -> Self {
+        Self {
+            on_save: false,
+            tab_size: 2,
+            insert_spaces: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PackageSettings {
+    /// Resolve packages only from the local data and cache directories.
+    pub offline: bool,
+    /// Data directory holding installed packages such as `@local/...`;
+    /// defaults to `<data dir>/typst/packages`.
+    pub package_path: Option<String>,
+    /// Directory for downloaded packages; defaults to `<cache dir>/typst/packages`.
+    pub package_cache_path: Option<String>,
+    /// Extra namespaces mapped to folders laid out as `<name>/<version>`.
+    pub namespaces: HashMap<String, String>,
+    /// Package index to browse: an HTTP(S) URL, a `file://` URL or a path.
+    /// Defaults to the Typst Universe index.
+    pub index_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CompileSettings {
+    /// Fixed date for `datetime.today()` and PDF metadata, as `YYYY-MM-DD`
+    /// or an RFC 3339 timestamp.
+    pub pinned_date: Option<String>,
+    /// Use `SOURCE_DATE_EPOCH` from the environment when it is set; takes
+    /// precedence over `pinned_date`.
+    pub source_date_epoch: bool,
+}
+
+impl CompileSettings {
+    /// The fixed compile time for reproducible output, or `None` to use the wall clock.
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        let epoch = match self.source_date_epoch {
+            true => std::env::var("SOURCE_DATE_EPOCH").ok(),
+            false => None,
+        };
+        self.timestamp_with(epoch.as_deref())
+    }
+
+    /// [`CompileSettings::timestamp`] with `source_date_epoch` as the value of
+    /// `SOURCE_DATE_EPOCH`; an invalid value falls back to the pinned date.
+    fn timestamp_with(&self, source_date_epoch: Option<&str>) -> Option<DateTime<Utc>> {
+        if let Some(epoch) = source_date_epoch {
+            match parse_source_date_epoch(epoch) {
+                Ok(datetime) => return Some(datetime),
+                Err(e) => tracing::warn!("Invalid SOURCE_DATE_EPOCH: {}", e),
+            }
+        }
+
+        let date = self.pinned_date.as_deref()?.trim();
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(date) {
+            return Some(datetime.with_timezone(&Utc));
+        }
+        match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(date) => Some(date.and_hms_opt(0, 0, 0)?.and_utc()),
+            Err(e) => {
+                tracing::warn!("Invalid pinned date {:?}: {}", date, e);
+                None
+            }
+        }
+    }
+}
+
+/// Parses a `SOURCE_DATE_EPOCH` value: seconds since the Unix epoch.
+pub fn parse_source_date_epoch(value: &str) -> Result<DateTime<Utc>, String> {
+    let secs = value.trim().parse::<i64>().map_err(|e| e.to_string())?;
+    DateTime::from_timestamp(secs, 0).ok_or_else(|| format!("{} is out of range", secs))
+}
+
+/// The local JSON-RPC remote control endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RemoteSettings {
+    pub enabled: bool,
+    /// Port on 127.0.0.1; 0 picks a free one.
+    pub port: u16,
+}
+
+impl Default for RemoteSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 23631,
+        }
+    }
+}
+
+/// Editor settings persisted as JSON in the app config directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    pub lsp: LspSettings,
+    pub format: FormatSettings,
+    pub packages: PackageSettings,
+    pub compile: CompileSettings,
+    pub remote: RemoteSettings,
+}
+
+impl Settings {
+    pub fn path(app: &AppHandle) -> Result<PathBuf, String> {
+        let dir = app
+            .path()
+            .app_config_dir()
+            .map_err(|e| format!("Failed to resolve config directory: {}", e))?;
+        Ok(dir.join(SETTINGS_FILE))
+    }
+
+    /// Loads settings from `path`, falling back to defaults if the file is missing or invalid.
+    pub fn load(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("Invalid settings file {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Reads settings from `path`, failing if the file is missing or invalid.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read settings file {}: {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid settings file {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create config directory: {}", e))?;
+        }
+
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize settings: {}", e))?;
+        std::fs::write(path, content).map_err(|e| format!("Failed to write settings: {}", e))
+    }
+}
+
+/// Reads the `sys.inputs` defaults of the project in `root` from a flat
+/// `key = "value"` table in `typst-inputs.toml`.
+pub fn load_project_inputs(root: &Path) -> HashMap<String, String> {
+    let path = root.join(INPUTS_FILE);
+    let Ok(content) = std::fs::read_to_string(&path) else {
+        return HashMap::new();
+    };
+    toml::from_str(&content).unwrap_or_else(|e| {
+        tracing::warn!("Invalid inputs file {}: {}", path.display(), e);
+        HashMap::new()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pinned(date: &str) -> CompileSettings {
+        CompileSettings { pinned_date: Some(date.to_string()), source_date_epoch: true }
+    }
+
+    #[test]
+    fn timestamp_parses_pinned_dates() {
+        let date = pinned("2024-02-29").timestamp_with(None).unwrap();
+        assert_eq!(date.to_rfc3339(), "2024-02-29T00:00:00+00:00");
+        let time = pinned("2024-02-29T23:30:00+02:00").timestamp_with(None).unwrap();
+        assert_eq!(time.to_rfc3339(), "2024-02-29T21:30:00+00:00");
+        assert_eq!(pinned("29.02.2024").timestamp_with(None), None);
+        assert_eq!(CompileSettings::default().timestamp_with(None), None);
+    }
+
+    #[test]
+    fn source_date_epoch_takes_precedence() {
+        let time = pinned("2024-02-29").timestamp_with(Some(" 1700000000\n")).unwrap();
+        assert_eq!(time.to_rfc3339(), "2023-11-14T22:13:20+00:00");
+    }
+
+    #[test]
+    fn invalid_source_date_epoch_falls_back_to_the_pinned_date() {
+        let date = pinned("2024-02-29").timestamp_with(None);
+        for epoch in ["", "yesterday", "1.5", "99999999999999999999", i64::MAX.to_string().as_str()] {
+            assert!(parse_source_date_epoch(epoch).is_err(), "{:?} was accepted", epoch);
+            assert_eq!(pinned("2024-02-29").timestamp_with(Some(epoch)), date);
+        }
+        assert_eq!(CompileSettings::default().timestamp_with(Some("yesterday")), None);
+    }
+}
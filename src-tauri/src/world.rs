@@ -1,16 +1,20 @@
-use chrono::{Datelike, Local, Utc};
+use chrono::{DateTime, Datelike, Local, Utc};
 use parking_lot::Mutex;
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 use typst::diag::{FileError, FileResult};
-use typst::foundations::{Bytes, Datetime};
+use typst::foundations::{Bytes, Datetime, Dict, Str, Value};
 use typst::syntax::{FileId, Source, VirtualPath};
 use typst::text::{Font, FontBook};
 use typst::utils::LazyHash;
 use typst::{Library, LibraryExt, World};
-use typst_kit::download::{Downloader, ProgressSink};
 use typst_kit::fonts::{FontSlot, Fonts};
 use typst_kit::package::PackageStorage;
 
+use crate::packages::{self, DownloadListener, FetchStatus, PackageFetcher};
+use crate::settings::{PackageSettings, Settings};
+
 pub struct EditorWorld {
     library: LazyHash<Library>,
     book: LazyHash<FontBook>,
@@ -19,11 +23,17 @@ pub struct EditorWorld {
     source: Mutex<Source>,
     root_dir: Mutex<Option<PathBuf>>,
     cache: Mutex<std::collections::HashMap<FileId, Source>>,
-    package_storage: Option<PackageStorage>,
+    package_storage: Option<Arc<PackageStorage>>,
+    package_settings: PackageSettings,
+    download_listener: Option<DownloadListener>,
+    package_fetcher: Option<Arc<PackageFetcher>>,
+    inputs: HashMap<String, String>,
+    /// Fixed compile time for reproducible builds.
+    now: Option<DateTime<Utc>>,
 }
 
 impl EditorWorld {
-    pub fn new(content: &str, file_path: Option<&PathBuf>) -> Self {
+    pub fn new(content: &str, file_path: Option<&PathBuf>, settings: &Settings) -> Self {
         tracing::info!("EditorWorld::new called with file_path: {:?}", file_path);
 
         let (main_id, root_dir) = if let Some(path) = file_path {
@@ -44,7 +54,7 @@ impl EditorWorld {
             .include_embedded_fonts(true)
             .search();
 
-        let package_storage = Self::init_package_storage();
+        let package_storage = Self::init_package_storage(&settings.packages);
 
         Self {
             library: LazyHash::new(Library::default()),
@@ -55,16 +65,51 @@ impl EditorWorld {
             root_dir: Mutex::new(root_dir),
             cache: Mutex::new(std::collections::HashMap::new()),
             package_storage,
+            package_settings: settings.packages.clone(),
+            download_listener: None,
+            package_fetcher: None,
+            inputs: HashMap::new(),
+            now: settings.compile.timestamp(),
+        }
+    }
+
+    /// Sets the key/value pairs the document reads through `sys.inputs`.
+    pub fn with_inputs(mut self, inputs: HashMap<String, String>) -> Self {
+        self.set_inputs(inputs);
+        self
+    }
+
+    /// Replaces `sys.inputs`, rebuilding the standard library only if they changed.
+    pub fn set_inputs(&mut self, inputs: HashMap<String, String>) {
+        if inputs == self.inputs {
+            return;
         }
+
+        let dict: Dict = inputs
+            .iter()
+            .map(|(key, value)| (Str::from(key.as_str()), Value::Str(value.as_str().into())))
+            .collect();
+        self.library = LazyHash::new(Library::builder().with_inputs(dict).build());
+        self.inputs = inputs;
     }
 
-    fn init_package_storage() -> Option<PackageStorage> {
-        // Create a downloader with user agent
-        let downloader = Downloader::new("typst-editor/0.1.0");
+    /// Downloads missing packages in the background through `fetcher` instead
+    /// of blocking the compile; without one, packages are prepared inline.
+    pub fn with_package_fetcher(mut self, fetcher: Arc<PackageFetcher>) -> Self {
+        self.package_fetcher = Some(fetcher);
+        self
+    }
+
+    /// Reports package download progress to `listener`.
+    pub fn with_download_listener(mut self, listener: DownloadListener) -> Self {
+        self.download_listener = Some(listener);
+        self
+    }
 
-        let storage = PackageStorage::new(None, None, downloader);
+    fn init_package_storage(settings: &PackageSettings) -> Option<Arc<PackageStorage>> {
+        let storage = packages::new_storage(settings);
         tracing::info!("Package storage initialized");
-        Some(storage)
+        Some(Arc::new(storage))
     }
 
     pub fn update_source(&self, content: &str, file_path: Option<&PathBuf>) {
@@ -90,18 +135,37 @@ impl EditorWorld {
         self.source.lock().clone()
     }
 
+    /// The on-disk path of a project or package file.
+    pub fn file_path(&self, id: FileId) -> Option<PathBuf> {
+        self.resolve_path(id)
+    }
+
     fn resolve_package_path(&self, id: FileId) -> Option<PathBuf> {
         // Get the package spec from the FileId
         let package_spec = id.package()?;
 
         let storage = self.package_storage.as_ref()?;
 
-        // Try to prepare (download) the package
-        tracing::info!("Preparing package: {:?}", package_spec);
+        let prepared = if let Some(dir) = packages::find_mapped(&self.package_settings, package_spec) {
+            Ok(dir)
+        } else if self.package_settings.offline {
+            // Offline mode never downloads, only the data and cache dirs are searched
+            packages::find_local(storage, package_spec)
+                .ok_or_else(|| "not in the local package cache (offline mode)".to_string())
+        } else if let Some(fetcher) = &self.package_fetcher {
+            // Missing packages are fetched in the background and reported as pending
+            packages::find_local(storage, package_spec).ok_or_else(|| {
+                fetcher.fetch(Arc::clone(storage), package_spec.clone());
+                "download pending".to_string()
+            })
+        } else {
+            // Try to prepare (download) the package
+            tracing::info!("Preparing package: {:?}", package_spec);
 
-        let mut progress = ProgressSink;
+            packages::prepare(storage, package_spec, self.download_listener.as_ref())
+        };
 
-        match storage.prepare_package(package_spec, &mut progress) {
+        match prepared {
             Ok(package_dir) => {
                 // Get the relative path within the package
                 // vpath contains the full path like "@preview/pkg:1.0.0/lib.typ"
@@ -147,9 +211,9 @@ impl EditorWorld {
     }
 
     fn resolve_path(&self, id: FileId) -> Option<PathBuf> {
-        // First try package resolution
-        if let Some(path) = self.resolve_package_path(id) {
-            return Some(path);
+        // Package files never fall back to the project directory
+        if id.package().is_some() {
+            return self.resolve_package_path(id);
         }
 
         // Then local file resolution
@@ -185,10 +249,43 @@ impl EditorWorld {
         }
     }
 
+    /// The error for a file that could not be resolved, explaining why a package is missing.
+    fn not_found(&self, id: FileId) -> FileError {
+        let path = id.vpath().as_rootless_path();
+        let Some(spec) = id.package() else {
+            return FileError::NotFound(path.into());
+        };
+
+        let status = self
+            .package_fetcher
+            .as_ref()
+            .map(|fetcher| fetcher.status(spec))
+            .unwrap_or(FetchStatus::Idle);
+
+        let hint = match status {
+            FetchStatus::Pending => {
+                return FileError::Other(Some(
+                    format!(
+                        "Package {} is being downloaded; the document will recompile when it is ready.",
+                        spec
+                    )
+                    .into(),
+                ));
+            }
+            FetchStatus::Failed(e) => format!("Download failed: {}", e),
+            FetchStatus::Idle if self.package_settings.offline => {
+                "Offline mode is on and the package is not cached.".to_string()
+            }
+            FetchStatus::Idle => "Check internet connection.".to_string(),
+        };
+
+        FileError::NotFound(
+            format!("Package not found: {} ({}). {}", spec.name, spec.version, hint).into(),
+        )
+    }
+
     fn read_file(&self, id: FileId) -> FileResult<Bytes> {
-        let path = self
-            .resolve_path(id)
-            .ok_or_else(|| FileError::NotFound(id.vpath().as_rootless_path().into()))?;
+        let path = self.resolve_path(id).ok_or_else(|| self.not_found(id))?;
 
         std::fs::read(&path)
             .map_err(|e| FileError::from_io(e, &path))
@@ -235,26 +332,7 @@ impl World for EditorWorld {
 
                     Ok(source)
                 }
-                None => {
-                    let path = id.vpath().as_rootless_path();
-                    let path_str = path.to_string_lossy();
-
-                    if path_str.starts_with('@') {
-                        if let Some(spec) = id.package() {
-                            Err(FileError::NotFound(
-                                format!(
-                                    "Package not found: {} ({}). Check internet connection.",
-                                    spec.name, spec.version
-                                )
-                                .into(),
-                            ))
-                        } else {
-                            Err(FileError::NotFound(path.into()))
-                        }
-                    } else {
-                        Err(FileError::NotFound(path.into()))
-                    }
-                }
+                None => Err(self.not_found(id)),
             }
         }
     }
@@ -273,19 +351,54 @@ impl World for EditorWorld {
     }
 
     fn today(&self, offset: Option<i64>) -> Option<Datetime> {
-        let now = Utc::now();
-        let local = match offset {
-            None => Local::now().naive_local(),
-            Some(hours) => {
-                let offset_secs = i32::try_from(hours).ok()?.checked_mul(3600)?;
-                let tz = chrono::FixedOffset::east_opt(offset_secs)?;
-                now.with_timezone(&tz).naive_local()
-            }
-        };
-        Datetime::from_ymd(
-            local.year(),
-            local.month().try_into().ok()?,
-            local.day().try_into().ok()?,
-        )
+        today(self.now, offset)
+    }
+}
+
+/// The date at `now`, or at the wall clock time, `offset` hours from UTC or
+/// in the local time zone.
+fn today(now: Option<DateTime<Utc>>, offset: Option<i64>) -> Option<Datetime> {
+    let pinned = now.is_some();
+    let now = now.unwrap_or_else(Utc::now);
+    let local = match offset {
+        // A pinned date must not depend on the machine's time zone
+        None if pinned => now.naive_utc(),
+        None => Local::now().naive_local(),
+        Some(hours) => {
+            let offset_secs = i32::try_from(hours).ok()?.checked_mul(3600)?;
+            let tz = chrono::FixedOffset::east_opt(offset_secs)?;
+            now.with_timezone(&tz).naive_local()
+        }
+    };
+    Datetime::from_ymd(
+        local.year(),
+        local.month().try_into().ok()?,
+        local.day().try_into().ok()?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u8, day: u8) -> Option<Datetime> {
+        Datetime::from_ymd(year, month, day)
+    }
+
+    #[test]
+    fn today_uses_the_pinned_time_in_utc() {
+        let now = DateTime::parse_from_rfc3339("2024-02-29T23:30:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(today(Some(now), None), date(2024, 2, 29));
+        assert_eq!(today(Some(now), Some(0)), date(2024, 2, 29));
+        assert_eq!(today(Some(now), Some(1)), date(2024, 3, 1));
+        assert_eq!(today(Some(now), Some(-23)), date(2024, 2, 29));
+    }
+
+    #[test]
+    fn today_rejects_out_of_range_offsets() {
+        let now = DateTime::from_timestamp(0, 0);
+        assert_eq!(today(now, Some(24)), None);
+        assert_eq!(today(now, Some(i64::MAX)), None);
+        assert_eq!(today(now, Some(i64::from(i32::MAX))), None);
     }
 }
@@ -13,7 +13,14 @@ import { FolderOpen, List, Search, Box, Settings, RefreshCw } from "lucide-react
 import "./App.css";
 import { Toolbar } from "./Toolbar";
 import { MenuBar } from "./MenuBar";
-import { typstCompletion, updateDocument } from "./TypstLsp";
+import {
+  contentChanges,
+  continueDocumentVersion,
+  typstCompletion,
+  TypstWorkspaceEdit,
+  updateDocument,
+  workspaceEditChanges,
+} from "./TypstLsp";
 import { useFileManager } from "./hooks/useFileManager";
 import { useKeyboardShortcuts } from "./hooks/useKeyboardShortcuts";
 import { FileTree } from "./components/FileTree";
@@ -501,7 +508,17 @@ function App() {
     setupAutoSave,
     clearAutoSave,
     removeFromRecentFiles,
-  } = useFileManager();
+  } = useFileManager({
+    // 保存时格式化过的内容写回编辑器；编辑器的更新会同步给语言服务器
+    onSaved: (saved, original) => {
+      const view = editorViewRef.current;
+      if (saved === original || view?.state.doc.toString() !== original) {
+        return;
+      }
+      setContent(saved);
+      sendCompile(saved, currentFilePathRef.current);
+    },
+  });
 
   // 键盘快捷键
   useKeyboardShortcuts({
@@ -522,7 +539,11 @@ function App() {
   const [sidebarWidth, setSidebarWidth] = useState(240);
   const timerRef = useRef<ReturnType<typeof setTimeout> | null>(null);
   const revisionRef = useRef(0);
+  // 远程控制已编译过的文件，打开时不再重复编译
+  const remoteOpenedPathRef = useRef<string | null>(null);
   const editorViewRef = useRef<EditorView | null>(null);
+  const currentFilePathRef = useRef<string | undefined>(undefined);
+  currentFilePathRef.current = currentFile?.path || undefined;
   const previewRef = useRef<HTMLDivElement | null>(null);
   const blockRefs = useRef(new Map<string, HTMLDivElement>());
   const [activeLine, setActiveLine] = useState(1);
@@ -565,7 +586,10 @@ function App() {
           setActiveLine(line);
         }
         if (update.docChanged) {
-          updateDocument(update.state.doc.toString());
+          updateDocument(
+            () => update.state.doc.toString(),
+            contentChanges(update.startState.doc, update.changes),
+          );
         }
       }),
     ];
@@ -811,6 +835,57 @@ function App() {
     [sendCompile, updateFileContent, setupAutoSave, currentFile?.path],
   );
 
+  // 语言服务器通过 workspace/applyEdit 请求的编辑
+  useEffect(() => {
+    const unlisten = listen<TypstWorkspaceEdit>("lsp-apply-edit", (event) => {
+      const view = editorViewRef.current;
+      if (!view) {
+        return;
+      }
+      const changes = workspaceEditChanges(view.state.doc, event.payload);
+      if (changes.length > 0) {
+        view.dispatch({ changes });
+      }
+    });
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  // 远程控制接口推送的文件和文本；后端已经编译过，这里只更新编辑器
+  useEffect(() => {
+    const unlistenOpen = listen<{ path: string; revision: number }>(
+      "remote-open-file",
+      (event) => {
+        revisionRef.current = Math.max(revisionRef.current, event.payload.revision);
+        remoteOpenedPathRef.current = event.payload.path;
+        openFile(event.payload.path);
+      },
+    );
+    const unlistenText = listen<{ text: string; revision: number; version: number | null }>(
+      "remote-set-text",
+      (event) => {
+        if (timerRef.current) {
+          clearTimeout(timerRef.current);
+        }
+        revisionRef.current = Math.max(revisionRef.current, event.payload.revision);
+        // 后端已把文本同步给语言服务器，之后的版本号接在它后面
+        if (event.payload.version !== null) {
+          continueDocumentVersion(event.payload.version);
+        }
+        setContent(event.payload.text);
+        updateFileContent(event.payload.text);
+        setupAutoSave(event.payload.text, 3000);
+      },
+    );
+
+    return () => {
+      unlistenOpen.then((fn) => fn());
+      unlistenText.then((fn) => fn());
+    };
+  }, [openFile, updateFileContent, setupAutoSave]);
+
   useEffect(() => {
     const unlistenPatch = listen<{
       revision: number;
@@ -832,7 +907,9 @@ function App() {
       },
     );
 
-    sendCompile(DEFAULT_CONTENT, currentFile?.path || undefined);
+    if (remoteOpenedPathRef.current !== currentFile?.path) {
+      sendCompile(DEFAULT_CONTENT, currentFile?.path || undefined);
+    }
 
     return () => {
       unlistenPatch.then((fn) => fn());
@@ -894,7 +971,11 @@ function App() {
   useEffect(() => {
     if (currentFile) {
       setContent(currentFile.content);
-      sendCompile(currentFile.content, currentFile.path);
+      if (remoteOpenedPathRef.current === currentFile.path) {
+        remoteOpenedPathRef.current = null;
+      } else {
+        sendCompile(currentFile.content, currentFile.path);
+      }
     }
   }, [currentFile?.path]);
 
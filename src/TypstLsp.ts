@@ -1,14 +1,43 @@
-import { Extension } from "@codemirror/state";
-import { CompletionContext, CompletionResult } from "@codemirror/autocomplete";
+import { ChangeSet, Extension, Text } from "@codemirror/state";
+import {
+  Completion,
+  CompletionContext,
+  CompletionResult,
+  insertCompletionText,
+  pickedCompletion,
+  snippet,
+} from "@codemirror/autocomplete";
+import { EditorView } from "@codemirror/view";
 import { invoke } from "@tauri-apps/api/core";
 
 const DOCUMENT_URI = "file:///workspace/main.typ";
 
+interface TypstRange {
+  start: { line: number; character: number };
+  end: { line: number; character: number };
+}
+
+interface TypstTextEdit {
+  range: TypstRange;
+  newText: string;
+}
+
+export interface TypstWorkspaceEdit {
+  changes: Record<string, TypstTextEdit[]>;
+}
+
 interface TypstCompletionItem {
   label: string;
   insertText?: string;
+  insertTextFormat?: number;
+  textEdit?: TypstTextEdit | { newText: string; insert: TypstRange; replace: TypstRange };
+  additionalTextEdits?: TypstTextEdit[];
   kind?: number;
   detail?: string;
+  documentation?: string | { kind: string; value: string };
+  sortText?: string;
+  filterText?: string;
+  data?: unknown;
 }
 
 interface TypstHover {
@@ -19,31 +48,123 @@ interface TypstHover {
   };
 }
 
-interface TypstLocation {
+interface TypstDefinitionLink {
   uri: string;
-  range: {
+  range: TypstRange;
+  selectionRange: TypstRange;
+  originSelectionRange?: TypstRange;
+}
+
+export interface TypstContentChange {
+  range?: {
     start: { line: number; character: number };
     end: { line: number; character: number };
   };
+  text: string;
 }
 
 let documentVersion = 1;
+// Set until the server has a full copy of the document; incremental changes
+// are only valid on top of one.
+let needsFullSync = true;
+// Updates are sent one after another so range changes arrive in order.
+let syncQueue: Promise<void> = Promise.resolve();
 
 export function typstLsp(): Extension {
   return [];
 }
 
-export async function updateDocument(content: string): Promise<void> {
-  try {
-    await invoke("lsp_update_document", {
-      uri: DOCUMENT_URI,
-      content,
-      version: documentVersion,
+/**
+ * Syncs the document with the language server. `changes` are sent as range
+ * edits; the full text from `getContent` is only sent for the first update,
+ * after a failed one, or when no changes are given.
+ */
+export function updateDocument(
+  getContent: () => string,
+  changes?: TypstContentChange[]
+): Promise<void> {
+  const version = documentVersion++;
+
+  syncQueue = syncQueue.then(async () => {
+    // Decided when the update runs, so a failure of an earlier queued update
+    // turns the following ones into full syncs
+    const incremental = !needsFullSync && changes !== undefined && changes.length > 0;
+    const content = incremental ? null : getContent();
+    try {
+      await invoke("lsp_update_document", {
+        uri: DOCUMENT_URI,
+        content,
+        version,
+        changes: incremental ? changes : undefined,
+      });
+      needsFullSync = false;
+    } catch (error) {
+      needsFullSync = true;
+      console.warn("Failed to update LSP document:", error);
+    }
+  });
+  return syncQueue;
+}
+
+/** Continues the document versions after `version`, used by the backend for text it sent itself. */
+export function continueDocumentVersion(version: number) {
+  documentVersion = Math.max(documentVersion, version + 1);
+}
+
+/**
+ * Saves `content` to `path` through the backend, which formats it first when
+ * format-on-save is enabled. The save is queued behind pending updates and
+ * takes the next document version, so the server sees the versions in order.
+ * Resolves to the content that was written.
+ */
+export function saveDocument(path: string, content: string): Promise<string> {
+  const version = documentVersion++;
+
+  const saved = syncQueue.then(() =>
+    invoke<string>("save_document", { path, content, uri: DOCUMENT_URI, version })
+  );
+  syncQueue = saved.then(
+    () => undefined,
+    () => undefined
+  );
+  return saved;
+}
+
+/** Converts CodeMirror changes into LSP range changes (UTF-16 line/character). */
+export function contentChanges(
+  startDoc: Text,
+  changes: ChangeSet
+): TypstContentChange[] {
+  const position = (offset: number) => {
+    const line = startDoc.lineAt(offset);
+    return { line: line.number - 1, character: offset - line.from };
+  };
+
+  const result: TypstContentChange[] = [];
+  changes.iterChanges((fromA, toA, _fromB, _toB, inserted) => {
+    result.push({
+      range: { start: position(fromA), end: position(toA) },
+      text: inserted.toString(),
     });
-    documentVersion++;
-  } catch (error) {
-    console.warn("Failed to update LSP document:", error);
-  }
+  });
+  // Ranges refer to the original document; applying them back to front
+  // keeps the earlier ranges valid
+  return result.reverse();
+}
+
+/** Converts an LSP position (UTF-16 line/character) into an offset in `doc`. */
+function positionOffset(doc: Text, position: { line: number; character: number }): number {
+  const line = doc.line(Math.min(position.line + 1, doc.lines));
+  return Math.min(line.from + position.character, line.to);
+}
+
+/** The editor document's part of a workspace edit, as CodeMirror changes. */
+export function workspaceEditChanges(doc: Text, edit: TypstWorkspaceEdit) {
+  return (edit.changes[DOCUMENT_URI] ?? []).map((textEdit) => ({
+    from: positionOffset(doc, textEdit.range.start),
+    to: positionOffset(doc, textEdit.range.end),
+    insert: textEdit.newText,
+  }));
 }
 
 export async function getCompletion(
@@ -64,6 +185,17 @@ export async function getCompletion(
   }
 }
 
+export async function resolveCompletion(
+  item: TypstCompletionItem
+): Promise<TypstCompletionItem> {
+  try {
+    return await invoke<TypstCompletionItem>("lsp_resolve_completion", { item });
+  } catch (error) {
+    console.warn("Completion resolve failed:", error);
+    return item;
+  }
+}
+
 export async function getHover(
   line: number,
   character: number
@@ -85,19 +217,113 @@ export async function getHover(
 export async function gotoDefinition(
   line: number,
   character: number
-): Promise<TypstLocation | null> {
+): Promise<TypstDefinitionLink[]> {
   try {
-    const result = await invoke<TypstLocation | null>("lsp_goto_definition", {
+    const result = await invoke<TypstDefinitionLink[]>("lsp_goto_definition", {
       uri: DOCUMENT_URI,
       line: line - 1,
       character,
       version: documentVersion,
     });
-    return result;
+    return result || [];
   } catch (error) {
     console.warn("Goto definition request failed:", error);
+    return [];
+  }
+}
+
+const INSERT_TEXT_FORMAT_SNIPPET = 2;
+// CodeMirror orders numbered fields ascending, so LSP's final `$0` goes last
+const FINAL_SNIPPET_FIELD = 9999;
+
+/** The range an item replaces, or `null` when it only carries insert text. */
+function completionRange(item: TypstCompletionItem): TypstRange | null {
+  const edit = item.textEdit;
+  if (!edit) {
     return null;
   }
+  return "range" in edit ? edit.range : edit.replace;
+}
+
+/**
+ * Rewrites an LSP snippet into CodeMirror's template syntax: `$1` becomes
+ * `${1}`, `$0` the last field, and Typst's own `#{` is escaped so it is not
+ * read as a field.
+ */
+function toCodeMirrorSnippet(text: string): string {
+  const field = (index: string) =>
+    index === "0" ? String(FINAL_SNIPPET_FIELD) : index;
+  return text
+    .replace(/#\{/g, "#\\{")
+    .replace(/\$(\d+)/g, (_match, index: string) => `\${${field(index)}}`)
+    .replace(/\$\{(\d+)([:}])/g, (_match, index: string, rest: string) => `\${${field(index)}${rest}`);
+}
+
+function documentationInfo(item: TypstCompletionItem): Node | null {
+  const documentation = item.documentation;
+  if (!documentation) {
+    return null;
+  }
+  const dom = document.createElement("div");
+  dom.className = "cm-completion-documentation";
+  dom.style.whiteSpace = "pre-wrap";
+  dom.textContent = typeof documentation === "string" ? documentation : documentation.value;
+  return dom;
+}
+
+function toCompletion(item: TypstCompletionItem): Completion {
+  // Resolved lazily when the info panel opens; applying uses it if it has arrived
+  let resolved: TypstCompletionItem | null = null;
+  let resolving: Promise<TypstCompletionItem> | null = null;
+  const resolve = () => {
+    resolving ??= resolveCompletion(item).then((result) => (resolved = result));
+    return resolving;
+  };
+
+  return {
+    label: item.filterText || item.label,
+    displayLabel: item.label,
+    detail: item.detail,
+    type: item.kind ? completionType(item.kind) : undefined,
+    info: item.documentation
+      ? () => documentationInfo(item)
+      : () => resolve().then(documentationInfo),
+    apply: (view: EditorView, completion: Completion, from: number, to: number) => {
+      const current = resolved ?? item;
+      let start = from;
+      let end = to;
+      const range = completionRange(current);
+      if (range) {
+        // The user may have typed on since the request; replace up to the cursor
+        start = positionOffset(view.state.doc, range.start);
+        end = Math.max(positionOffset(view.state.doc, range.end), to);
+      }
+
+      const additional = current.additionalTextEdits ?? [];
+      if (additional.length > 0) {
+        const changes = view.state.changes(
+          additional.map((edit) => ({
+            from: positionOffset(view.state.doc, edit.range.start),
+            to: positionOffset(view.state.doc, edit.range.end),
+            insert: edit.newText,
+          })),
+        );
+        view.dispatch({ changes });
+        start = changes.mapPos(start, -1);
+        end = changes.mapPos(end, 1);
+      }
+
+      const text = current.textEdit?.newText ?? current.insertText ?? current.label;
+      if (current.insertTextFormat === INSERT_TEXT_FORMAT_SNIPPET) {
+        snippet(toCodeMirrorSnippet(text))(view, completion, start, end);
+      } else {
+        view.dispatch({
+          ...insertCompletionText(view.state, text, start, end),
+          annotations: pickedCompletion.of(completion),
+        });
+      }
+    },
+  };
 }
 
 export function typstCompletion(context: CompletionContext): Promise<CompletionResult | null> {
@@ -110,42 +336,40 @@ export function typstCompletion(context: CompletionContext): Promise<CompletionR
       return null;
     }
 
+    // Filter against the text the server's edit replaces, or the word before the cursor
+    const range = items.map(completionRange).find((r) => r !== null);
+    const word = context.matchBefore(/[\p{L}\p{N}_-]*/u);
+    const from = range
+      ? Math.min(positionOffset(context.state.doc, range.start), context.pos)
+      : word?.from ?? context.pos;
+
     return {
-      from: context.pos,
-      options: items.map((item) => ({
-        label: item.label,
-        apply: item.insertText || item.label,
-        detail: item.detail,
-        kind: item.kind ? mapKind(item.kind) : undefined,
-      })),
+      from,
+      options: items.map(toCompletion),
     };
   });
 }
 
-function mapKind(kind: number): number {
-  const kindMap: Record<number, number> = {
-    1: 0,
-    2: 1,
-    3: 2,
-    4: 3,
-    5: 4,
-    6: 5,
-    7: 6,
-    8: 7,
-    9: 8,
-    10: 9,
-    11: 10,
-    12: 11,
-    13: 12,
-    14: 13,
-    15: 14,
-    16: 15,
-    17: 16,
-    18: 17,
-    19: 18,
-    20: 19,
-    21: 20,
-    22: 21,
+/** Maps an LSP `CompletionItemKind` to the CodeMirror completion type (its icon). */
+function completionType(kind: number): string | undefined {
+  const typeMap: Record<number, string> = {
+    2: "method",
+    3: "function",
+    4: "function",
+    5: "property",
+    6: "variable",
+    7: "class",
+    8: "interface",
+    9: "namespace",
+    10: "property",
+    12: "constant",
+    13: "enum",
+    14: "keyword",
+    15: "text",
+    20: "enum",
+    21: "constant",
+    22: "type",
+    25: "type",
   };
-  return kindMap[kind] ?? 1;
+  return typeMap[kind];
 }
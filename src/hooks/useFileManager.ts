@@ -1,7 +1,8 @@
 import { useState, useCallback, useEffect, useRef } from "react";
 import { open, save } from "@tauri-apps/plugin-dialog";
-import { readTextFile, writeTextFile, readDir } from "@tauri-apps/plugin-fs";
+import { readTextFile, readDir } from "@tauri-apps/plugin-fs";
 import { join, basename } from "@tauri-apps/api/path";
+import { saveDocument } from "../TypstLsp";
 
 export type FileNode = {
   name: string;
@@ -22,7 +23,12 @@ export type OpenFile = {
 const RECENT_FILES_KEY = "typst-editor-recent-files";
 const MAX_RECENT_FILES = 10;
 
-export function useFileManager() {
+export type FileManagerOptions = {
+  // 保存后调用；格式化后的内容与保存前的内容不同时，由调用方更新编辑器
+  onSaved?: (saved: string, original: string) => void;
+};
+
+export function useFileManager(options: FileManagerOptions = {}) {
   const [currentFile, setCurrentFile] = useState<OpenFile | null>(null);
   const [workspaceRoot, setWorkspaceRoot] = useState<string | null>(null);
   const [fileTree, setFileTree] = useState<FileNode[]>([]);
@@ -30,6 +36,8 @@ export function useFileManager() {
   const [isLoading, setIsLoading] = useState(false);
   const [error, setError] = useState<string | null>(null);
   const autoSaveTimerRef = useRef<ReturnType<typeof setTimeout> | null>(null);
+  const onSavedRef = useRef(options.onSaved);
+  onSavedRef.current = options.onSaved;
 
   // 加载最近打开的文件列表
   useEffect(() => {
@@ -252,19 +260,20 @@ export function useFileManager() {
         }
       }
 
-      await writeTextFile(targetPath, content);
+      const saved = await saveDocument(targetPath, content);
       const fileName = await basename(targetPath);
 
       const savedFile: OpenFile = {
         path: targetPath,
         name: fileName,
-        content,
+        content: saved,
         isDirty: false,
         isNew: false,
       };
 
       setCurrentFile(savedFile);
       addToRecentFiles(targetPath);
+      onSavedRef.current?.(saved, content);
 
       // 如果在工作区内，刷新文件树
       if (workspaceRoot && targetPath.startsWith(workspaceRoot)) {
@@ -292,19 +301,20 @@ export function useFileManager() {
       });
 
       if (selected) {
-        await writeTextFile(selected, content);
+        const saved = await saveDocument(selected, content);
         const fileName = await basename(selected);
 
         const savedFile: OpenFile = {
           path: selected,
           name: fileName,
-          content,
+          content: saved,
           isDirty: false,
           isNew: false,
         };
 
         setCurrentFile(savedFile);
         addToRecentFiles(selected);
+        onSavedRef.current?.(saved, content);
         return true;
       }
       return false;